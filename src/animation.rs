@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use hecs::World;
+
+use crate::components::{AnimState, Animator, Position, Speed};
+
+// Slower than this, in pixels a second, and a character is standing still.
+// Keeps the last bit of a collision's push from showing as a walk.
+const MOVING: f32 = 5.0;
+// How far past its walking speed a character has to go to be running.
+const RUN_FACTOR: f32 = 1.25;
+
+// Picks each character's movement state from how far it moved this tick,
+// then steps its animation. Runs after positions are synced.
+pub fn animate(world: &mut World, tick: Duration) {
+    for (_id, (position, speed, animator)) in
+        &mut world.query::<(&Position, Option<&Speed>, &mut Animator)>()
+    {
+        let moved = (position.current - position.previous).magnitude() / tick.as_secs_f32();
+        let state = match speed {
+            _ if moved < MOVING => AnimState::Idle,
+            Some(speed) if moved > speed.0 * RUN_FACTOR => AnimState::Run,
+            _ => AnimState::Walk,
+        };
+        animator.set_state(state);
+        animator.advance(tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{Anim, AnimationData, Direction};
+    use tetra::graphics::Rectangle;
+    use tetra::math::Vec2;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    fn animator() -> Animator {
+        let mut animations = AnimationData::default();
+        let frames = [Rectangle::new(0.0, 0.0, 8.0, 8.0), Rectangle::new(8.0, 0.0, 8.0, 8.0)];
+        animations.insert(AnimState::Walk, Direction::Down, Anim::new(&frames, TICK / 2));
+        animations.insert(
+            AnimState::Interact,
+            Direction::Down,
+            Anim::new(&frames[1..], TICK / 2),
+        );
+        Animator::new(animations)
+    }
+
+    fn moved_by(distance: f32, speed: f32) -> AnimState {
+        let mut world = World::new();
+        let mut position = Position::new(Vec2::zero());
+        position.current.x = distance;
+        let entity = world.spawn((position, Speed(speed), animator()));
+        animate(&mut world, TICK);
+        let animator = world.get::<Animator>(entity).unwrap();
+        animator.state
+    }
+
+    #[test]
+    fn speed_picks_the_state() {
+        assert_eq!(moved_by(0.0, 100.0), AnimState::Idle);
+        assert_eq!(moved_by(10.0, 100.0), AnimState::Walk);
+        assert_eq!(moved_by(20.0, 100.0), AnimState::Run);
+    }
+
+    #[test]
+    fn missing_states_fall_back() {
+        let mut animator = animator();
+        // no idle animation: stand on the first walk frame
+        assert_eq!(animator.current_frame().unwrap().x, 0.0);
+        // no run animation: run with the walk cycle
+        animator.set_state(AnimState::Run);
+        animator.advance(TICK);
+        assert_eq!(animator.current_frame().unwrap().x, 8.0);
+    }
+
+    #[test]
+    fn actions_play_once() {
+        let mut animator = animator();
+        animator.set_state(AnimState::Walk);
+        animator.perform(AnimState::Interact);
+        assert_eq!(animator.action, Some(AnimState::Interact));
+        animator.advance(TICK);
+        assert_eq!(animator.action, None);
+        assert_eq!(animator.current_frame().unwrap().x, 0.0);
+        // nothing to play facing up, so walking carries on
+        animator.direction = Direction::Up;
+        animator.perform(AnimState::Interact);
+        assert_eq!(animator.action, None);
+    }
+}
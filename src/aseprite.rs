@@ -0,0 +1,200 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::de::{Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use tetra::graphics::Rectangle;
+
+use crate::components::Anim;
+
+// The parts of Aseprite's "Export Sprite Sheet" JSON we use. Either of its
+// layouts works: `frames` as an array, or as a hash keyed by frame name.
+#[derive(Deserialize)]
+struct SpriteSheet {
+    frames: Frames,
+    meta: Meta,
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    frame: FrameRect,
+    // milliseconds
+    duration: u64,
+}
+
+#[derive(Deserialize)]
+struct FrameRect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    #[serde(rename = "frameTags", default)]
+    frame_tags: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+    from: usize,
+    // inclusive
+    to: usize,
+    #[serde(default)]
+    direction: TagDirection,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TagDirection {
+    Forward,
+    Reverse,
+    Pingpong,
+}
+
+impl Default for TagDirection {
+    fn default() -> TagDirection {
+        TagDirection::Forward
+    }
+}
+
+// Frames in the order Aseprite wrote them. A hash keeps its document order
+// here, which is frame order.
+struct Frames(Vec<Frame>);
+
+impl<'de> Deserialize<'de> for Frames {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Frames, D::Error> {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = Frames;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an array or hash of frames")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Frames, A::Error> {
+                let mut frames = Vec::new();
+                while let Some(frame) = seq.next_element()? {
+                    frames.push(frame);
+                }
+                Ok(Frames(frames))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Frames, A::Error> {
+                let mut frames = Vec::new();
+                while let Some((_name, frame)) = map.next_entry::<String, Frame>()? {
+                    frames.push(frame);
+                }
+                Ok(Frames(frames))
+            }
+        }
+
+        deserializer.deserialize_any(FramesVisitor)
+    }
+}
+
+// One animation per frame tag, named after the tag.
+pub fn parse(json: &str) -> Result<Vec<(String, Anim)>, String> {
+    let sheet: SpriteSheet = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    let frames: Vec<_> = sheet
+        .frames
+        .0
+        .iter()
+        .map(|frame| {
+            let rect = &frame.frame;
+            (
+                Rectangle::new(rect.x, rect.y, rect.w, rect.h),
+                Duration::from_millis(frame.duration),
+            )
+        })
+        .collect();
+    sheet
+        .meta
+        .frame_tags
+        .iter()
+        .map(|tag| {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(format!(
+                    "tag {} covers frames {} to {}, but there are only {}",
+                    tag.name,
+                    tag.from,
+                    tag.to,
+                    frames.len()
+                ));
+            }
+            let forward = &frames[tag.from..=tag.to];
+            let tagged: Vec<_> = match tag.direction {
+                TagDirection::Forward => forward.to_vec(),
+                TagDirection::Reverse => forward.iter().rev().copied().collect(),
+                // there and back without playing either end twice
+                TagDirection::Pingpong => forward
+                    .iter()
+                    .chain(forward.iter().rev().skip(1).take(forward.len().saturating_sub(2)))
+                    .copied()
+                    .collect(),
+            };
+            Ok((tag.name.clone(), Anim::timed(tagged)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH_EXPORT: &str = r#"{
+        "frames": {
+            "walk 0.aseprite": { "frame": { "x": 0, "y": 0, "w": 19, "h": 32 }, "duration": 100 },
+            "walk 1.aseprite": { "frame": { "x": 19, "y": 0, "w": 19, "h": 32 }, "duration": 150 },
+            "walk 2.aseprite": { "frame": { "x": 38, "y": 0, "w": 19, "h": 32 }, "duration": 100 },
+            "walk 3.aseprite": { "frame": { "x": 0, "y": 32, "w": 19, "h": 32 }, "duration": 400 }
+        },
+        "meta": {
+            "app": "http://www.aseprite.org/",
+            "frameTags": [
+                { "name": "walk_down", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "idle_down", "from": 3, "to": 3, "direction": "forward" }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn tags_become_animations() {
+        let anims = parse(HASH_EXPORT).unwrap();
+        let names: Vec<_> = anims.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["walk_down", "idle_down"]);
+        let walk = &anims[0].1;
+        let xs: Vec<_> = walk.frames.iter().map(|frame| frame.x).collect();
+        assert_eq!(xs, [0.0, 19.0, 38.0, 19.0]);
+        assert_eq!(walk.durations[1], Duration::from_millis(150));
+        assert_eq!(anims[1].1.frames[0], Rectangle::new(0.0, 32.0, 19.0, 32.0));
+    }
+
+    #[test]
+    fn array_export_and_reverse_tags() {
+        let json = r#"{
+            "frames": [
+                { "filename": "a", "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 100 },
+                { "filename": "b", "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "duration": 200 }
+            ],
+            "meta": {
+                "frameTags": [ { "name": "walk_up", "from": 0, "to": 1, "direction": "reverse" } ]
+            }
+        }"#;
+        let anims = parse(json).unwrap();
+        assert_eq!(anims[0].1.frames[0].x, 8.0);
+        assert_eq!(anims[0].1.durations[0], Duration::from_millis(200));
+    }
+
+    #[test]
+    fn tags_past_the_last_frame_are_rejected() {
+        let json = r#"{
+            "frames": [ { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 100 } ],
+            "meta": { "frameTags": [ { "name": "walk_up", "from": 0, "to": 3 } ] }
+        }"#;
+        assert!(parse(json).is_err());
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use tetra::input::{self, GamepadButton, GamepadStick, Key};
+use tetra::math::Vec2;
+use tetra::{Context, TetraError};
+
+use crate::map::map_error;
+
+// Bindings in here replace the built-in ones action by action. Rebinding in
+// game writes the whole set back to it.
+pub const BINDINGS_PATH: &str = "bindings.ron";
+
+const DEFAULT_BINDINGS: &str = include_str!("../resources/bindings.ron");
+
+// Everything a key or gamepad button can be bound to. Gameplay asks about
+// these, never keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    PreviousSkin,
+    NextSkin,
+    Interact,
+    Pause,
+    Quit,
+    Quicksave,
+    Quickload,
+    ZoomIn,
+    ZoomOut,
+    // prints how long each system took
+    Timings,
+    // picks an action and asks for new keys for it
+    Rebind,
+}
+
+impl Action {
+    pub const ALL: [Action; 15] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::PreviousSkin,
+        Action::NextSkin,
+        Action::Interact,
+        Action::Pause,
+        Action::Quit,
+        Action::Quicksave,
+        Action::Quickload,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::Timings,
+        Action::Rebind,
+    ];
+}
+
+// Keys that can be named in a bindings file, by their tetra names.
+const NAMED_KEYS: &[Key] = &[
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+    Key::Num0,
+    Key::Num1,
+    Key::Num2,
+    Key::Num3,
+    Key::Num4,
+    Key::Num5,
+    Key::Num6,
+    Key::Num7,
+    Key::Num8,
+    Key::Num9,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::Up,
+    Key::Down,
+    Key::Left,
+    Key::Right,
+    Key::Space,
+    Key::Enter,
+    Key::Escape,
+    Key::Tab,
+    Key::Backspace,
+    Key::LeftShift,
+    Key::RightShift,
+    Key::LeftCtrl,
+    Key::RightCtrl,
+    Key::LeftAlt,
+    Key::RightAlt,
+    Key::LeftBracket,
+    Key::RightBracket,
+    Key::Comma,
+    Key::Period,
+    Key::Slash,
+    Key::Backslash,
+    Key::Semicolon,
+    Key::Quote,
+    Key::Minus,
+    Key::Equals,
+    Key::Backquote,
+];
+
+// Gamepad buttons likewise. Up, Down, Left and Right are the D-pad.
+const NAMED_BUTTONS: &[GamepadButton] = &[
+    GamepadButton::A,
+    GamepadButton::B,
+    GamepadButton::X,
+    GamepadButton::Y,
+    GamepadButton::Up,
+    GamepadButton::Down,
+    GamepadButton::Left,
+    GamepadButton::Right,
+    GamepadButton::LeftShoulder,
+    GamepadButton::LeftTrigger,
+    GamepadButton::LeftStick,
+    GamepadButton::RightShoulder,
+    GamepadButton::RightTrigger,
+    GamepadButton::RightStick,
+    GamepadButton::Start,
+    GamepadButton::Back,
+    GamepadButton::Guide,
+];
+
+fn from_name<T: Copy + Debug>(named: &[T], name: &str) -> Option<T> {
+    named.iter().copied().find(|input| input_name(input) == name)
+}
+
+fn input_name<T: Debug>(input: &T) -> String {
+    format!("{:?}", input)
+}
+
+// A bindings file as written: key and gamepad button names for each action,
+// e.g. `MoveUp: ["W", "Up"]`.
+#[derive(Default, Serialize, Deserialize)]
+struct BindingsFile {
+    #[serde(default)]
+    keys: BTreeMap<Action, Vec<String>>,
+    #[serde(default)]
+    buttons: BTreeMap<Action, Vec<String>>,
+    // how far a stick has to be pushed before it counts, from 0 to 1
+    #[serde(default)]
+    deadzone: Option<f32>,
+}
+
+pub struct Bindings {
+    keys: HashMap<Action, Vec<Key>>,
+    buttons: HashMap<Action, Vec<GamepadButton>>,
+    deadzone: f32,
+    // connected gamepads; any of them can play
+    gamepads: Vec<usize>,
+}
+
+impl Bindings {
+    // The built-in bindings, then whatever `BINDINGS_PATH` changes.
+    pub fn load() -> tetra::Result<Bindings> {
+        let mut bindings = Bindings::default_bindings()?;
+        let path = Path::new(BINDINGS_PATH);
+        if path.is_file() {
+            let text = fs::read_to_string(path).map_err(|reason| TetraError::FailedToLoadAsset {
+                reason,
+                path: path.to_path_buf(),
+            })?;
+            bindings.apply(&text, path)?;
+        }
+        Ok(bindings)
+    }
+
+    fn default_bindings() -> tetra::Result<Bindings> {
+        let mut bindings = Bindings {
+            keys: HashMap::new(),
+            buttons: HashMap::new(),
+            deadzone: 0.0,
+            gamepads: Vec::new(),
+        };
+        bindings.apply(DEFAULT_BINDINGS, Path::new("resources/bindings.ron"))?;
+        Ok(bindings)
+    }
+
+    // Binds every action the file lists, leaving the rest as they were.
+    fn apply(&mut self, text: &str, path: &Path) -> tetra::Result {
+        let file: BindingsFile =
+            ron::de::from_str(text).map_err(|err| map_error(path, err.to_string()))?;
+        for (action, names) in file.keys {
+            self.keys.insert(action, parse_names(NAMED_KEYS, &names, action, path)?);
+        }
+        for (action, names) in file.buttons {
+            self.buttons.insert(action, parse_names(NAMED_BUTTONS, &names, action, path)?);
+        }
+        if let Some(deadzone) = file.deadzone {
+            if !(0.0..1.0).contains(&deadzone) {
+                return Err(map_error(path, format!("deadzone {} isn't from 0 to 1", deadzone)));
+            }
+            self.deadzone = deadzone;
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> tetra::Result {
+        let file = BindingsFile {
+            keys: names(&self.keys),
+            buttons: names(&self.buttons),
+            deadzone: Some(self.deadzone),
+        };
+        let text = ron::ser::to_string_pretty(&file, PrettyConfig::default())
+            .map_err(|err| map_error(path, err.to_string()))?;
+        fs::write(path, text).map_err(|reason| TetraError::FailedToLoadAsset {
+            reason,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn keys(&self, action: Action) -> &[Key] {
+        self.keys.get(&action).map_or(&[][..], Vec::as_slice)
+    }
+
+    pub fn buttons(&self, action: Action) -> &[GamepadButton] {
+        self.buttons.get(&action).map_or(&[][..], Vec::as_slice)
+    }
+
+    // Replaces whatever keys `action` was bound to. Its buttons stay.
+    pub fn bind(&mut self, action: Action, keys: Vec<Key>) {
+        self.keys.insert(action, keys);
+    }
+
+    // Other actions bound to one of `action`'s keys. Both still happen when
+    // such a key is pressed.
+    pub fn clashes(&self, action: Action) -> Vec<(Key, Action)> {
+        let mut clashes = Vec::new();
+        for &key in self.keys(action) {
+            for &other in &Action::ALL {
+                if other != action && self.keys(other).contains(&key) {
+                    clashes.push((key, other));
+                }
+            }
+        }
+        clashes
+    }
+
+    pub fn gamepad_added(&mut self, id: usize) {
+        if !self.gamepads.contains(&id) {
+            self.gamepads.push(id);
+        }
+    }
+
+    pub fn gamepad_removed(&mut self, id: usize) {
+        self.gamepads.retain(|&gamepad| gamepad != id);
+    }
+
+    pub fn is_down(&self, ctx: &Context, action: Action) -> bool {
+        self.keys(action).iter().any(|&key| input::is_key_down(ctx, key))
+            || self.any_button(action, |id, button| {
+                input::is_gamepad_button_down(ctx, id, button)
+            })
+    }
+
+    pub fn is_pressed(&self, ctx: &Context, action: Action) -> bool {
+        self.keys(action).iter().any(|&key| input::is_key_pressed(ctx, key))
+            || self.any_button(action, |id, button| {
+                input::is_gamepad_button_pressed(ctx, id, button)
+            })
+    }
+
+    fn any_button(&self, action: Action, test: impl Fn(usize, GamepadButton) -> bool) -> bool {
+        self.gamepads
+            .iter()
+            .any(|&id| self.buttons(action).iter().any(|&button| test(id, button)))
+    }
+
+    // The left stick pushed furthest out of every connected gamepad's, at
+    // most one long, and zero inside the deadzone.
+    pub fn stick(&self, ctx: &Context) -> Vec2<f32> {
+        let furthest = self
+            .gamepads
+            .iter()
+            .map(|&id| input::get_gamepad_stick_position(ctx, id, GamepadStick::LeftStick))
+            .fold(Vec2::zero(), |furthest: Vec2<f32>, stick| {
+                if stick.magnitude() > furthest.magnitude() {
+                    stick
+                } else {
+                    furthest
+                }
+            });
+        outside_deadzone(furthest, self.deadzone)
+    }
+}
+
+// Rescales a stick so it goes from zero at the edge of the deadzone to one at
+// full tilt, keeping its direction; slight tilts still move slowly.
+fn outside_deadzone(stick: Vec2<f32>, deadzone: f32) -> Vec2<f32> {
+    let tilt = stick.magnitude();
+    if tilt <= deadzone {
+        return Vec2::zero();
+    }
+    let scaled = ((tilt - deadzone) / (1.0 - deadzone)).min(1.0);
+    stick * (scaled / tilt)
+}
+
+fn parse_names<T: Copy + Debug>(
+    named: &[T],
+    names: &[String],
+    action: Action,
+    path: &Path,
+) -> tetra::Result<Vec<T>> {
+    names
+        .iter()
+        .map(|name| {
+            from_name(named, name).ok_or_else(|| {
+                map_error(path, format!("{:?} is bound to unknown input {}", action, name))
+            })
+        })
+        .collect()
+}
+
+fn names<T: Debug>(bound: &HashMap<Action, Vec<T>>) -> BTreeMap<Action, Vec<String>> {
+    bound
+        .iter()
+        .map(|(&action, inputs)| (action, inputs.iter().map(input_name).collect()))
+        .collect()
+}
+
+fn key_list(keys: &[Key]) -> String {
+    if keys.is_empty() {
+        return "nothing".to_owned();
+    }
+    keys.iter().map(input_name).collect::<Vec<_>>().join(", ")
+}
+
+// Rebinding in progress. Up and Down pick an action and Enter starts on it;
+// keys pressed after that are collected until Enter binds them in place of
+// the old ones, or Escape leaves the old ones alone. Escape while picking
+// finishes rebinding.
+pub struct Rebinding {
+    selected: usize,
+    // keys pressed so far for the selected action, once Enter was pressed
+    collecting: Option<Vec<Key>>,
+    // whether anything was bound, so whether there's anything to save
+    pub changed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RebindStep {
+    Waiting,
+    Bound(Action),
+    Finished,
+}
+
+impl Rebinding {
+    pub fn start() -> Rebinding {
+        Rebinding {
+            selected: 0,
+            collecting: None,
+            changed: false,
+        }
+    }
+
+    // The action picked, or being given new keys.
+    pub fn action(&self) -> Action {
+        Action::ALL[self.selected]
+    }
+
+    pub fn press(&mut self, bindings: &mut Bindings, key: Key) -> RebindStep {
+        let keys = match self.collecting.as_mut() {
+            Some(keys) => keys,
+            None => return self.pick(key),
+        };
+        match key {
+            Key::Escape => self.collecting = None,
+            Key::Enter if keys.is_empty() => self.collecting = None,
+            Key::Enter => {
+                let keys = mem::take(keys);
+                self.collecting = None;
+                bindings.bind(self.action(), keys);
+                self.changed = true;
+                return RebindStep::Bound(self.action());
+            }
+            _ if keys.contains(&key) => {}
+            _ => keys.push(key),
+        }
+        RebindStep::Waiting
+    }
+
+    fn pick(&mut self, key: Key) -> RebindStep {
+        let count = Action::ALL.len();
+        match key {
+            Key::Up => self.selected = (self.selected + count - 1) % count,
+            Key::Down => self.selected = (self.selected + 1) % count,
+            Key::Enter => self.collecting = Some(Vec::new()),
+            Key::Escape => return RebindStep::Finished,
+            _ => {}
+        }
+        RebindStep::Waiting
+    }
+
+    // What to show the player for the step rebinding is on.
+    pub fn prompt(&self, bindings: &Bindings) -> String {
+        match &self.collecting {
+            None => format!(
+                "{:?} is bound to {} (Up/Down picks, Enter rebinds, Escape finishes)",
+                self.action(),
+                key_list(bindings.keys(self.action()))
+            ),
+            Some(keys) => format!(
+                "new keys for {:?}: {} (Enter binds, Escape cancels)",
+                self.action(),
+                key_list(keys)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_action_has_a_default() {
+        let bindings = Bindings::default_bindings().unwrap();
+        for &action in &Action::ALL {
+            assert!(!bindings.keys(action).is_empty(), "{:?} has no key", action);
+        }
+    }
+
+    #[test]
+    fn a_file_only_changes_what_it_lists() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        let text = r#"(keys: { MoveUp: ["Up", "K"] })"#;
+        bindings.apply(text, Path::new("bindings.ron")).unwrap();
+        assert_eq!(bindings.keys(Action::MoveUp), [Key::Up, Key::K]);
+        assert_eq!(bindings.keys(Action::MoveDown), [Key::S, Key::Down]);
+    }
+
+    #[test]
+    fn buttons_and_deadzone_load() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        let text = r#"(buttons: { Interact: ["X", "RightTrigger"] }, deadzone: 0.5)"#;
+        bindings.apply(text, Path::new("bindings.ron")).unwrap();
+        let buttons = bindings.buttons(Action::Interact);
+        assert_eq!(buttons, [GamepadButton::X, GamepadButton::RightTrigger]);
+        assert_eq!(bindings.buttons(Action::MoveUp), [GamepadButton::Up]);
+        assert_eq!(bindings.deadzone, 0.5);
+        let text = r#"(deadzone: 1.5)"#;
+        assert!(bindings.apply(text, Path::new("bindings.ron")).is_err());
+    }
+
+    #[test]
+    fn sticks_scale_from_the_deadzone() {
+        assert_eq!(outside_deadzone(Vec2::new(0.1, 0.1), 0.2), Vec2::zero());
+        let half = outside_deadzone(Vec2::new(0.0, -0.6), 0.2);
+        assert!((half.y + 0.5).abs() < 1e-6 && half.x == 0.0);
+        let full = outside_deadzone(Vec2::new(1.0, 1.0), 0.2);
+        assert!((full.magnitude() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        let text = r#"(keys: { Pause: ["Pouse"] })"#;
+        assert!(bindings.apply(text, Path::new("bindings.ron")).is_err());
+    }
+
+    #[test]
+    fn rebinding_changes_one_action() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        let mut rebinding = Rebinding::start();
+        for &key in &[Key::Down, Key::Enter, Key::J, Key::Down, Key::J] {
+            assert_eq!(rebinding.press(&mut bindings, key), RebindStep::Waiting);
+        }
+        let step = rebinding.press(&mut bindings, Key::Enter);
+        assert_eq!(step, RebindStep::Bound(Action::MoveDown));
+        assert_eq!(bindings.keys(Action::MoveDown), [Key::J, Key::Down]);
+        assert_eq!(bindings.keys(Action::MoveUp), [Key::W, Key::Up]);
+        assert_eq!(rebinding.press(&mut bindings, Key::Escape), RebindStep::Finished);
+        assert!(rebinding.changed);
+    }
+
+    #[test]
+    fn escape_cancels_rebinding() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        let mut rebinding = Rebinding::start();
+        for &key in &[Key::Enter, Key::J, Key::Escape] {
+            assert_eq!(rebinding.press(&mut bindings, key), RebindStep::Waiting);
+        }
+        assert_eq!(bindings.keys(Action::MoveUp), [Key::W, Key::Up]);
+        assert_eq!(rebinding.press(&mut bindings, Key::Escape), RebindStep::Finished);
+        assert!(!rebinding.changed);
+    }
+
+    #[test]
+    fn shared_keys_clash() {
+        let mut bindings = Bindings::default_bindings().unwrap();
+        assert!(bindings.clashes(Action::Interact).is_empty());
+        bindings.bind(Action::Interact, vec![Key::E, Key::P]);
+        assert_eq!(bindings.clashes(Action::Interact), [(Key::P, Action::Pause)]);
+    }
+}
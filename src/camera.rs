@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use hecs::World;
+use tetra::graphics::Camera;
+use tetra::math::Vec2;
+
+use crate::components::{CameraFollow, Position};
+use crate::SCALE;
+
+// The most screen pixels a map pixel can be zoomed to, each way.
+const MAX_PIXEL_SCALE: f32 = 6.0;
+
+// Moves each camera towards the entity it's on, then keeps it from showing
+// anything past the edges of the map. `map_size` is in map pixels.
+pub fn follow(
+    world: &mut World,
+    map_size: Vec2<f32>,
+    viewport: Vec2<f32>,
+    blend: f32,
+    delta: Duration,
+) {
+    for (_id, (camera, position, follow)) in
+        &mut world.query::<(&mut Camera, &Position, Option<&CameraFollow>)>()
+    {
+        let follow = follow.copied().unwrap_or_default();
+        let target = position.interpolated(blend);
+        let center = camera.position / SCALE;
+        let wanted = Vec2::new(
+            track(center.x, target.x, follow.deadzone.0),
+            track(center.y, target.y, follow.deadzone.1),
+        );
+        // the same smoothing whatever the frame rate
+        let center = if follow.half_life > 0.0 {
+            let closed = 1.0 - 0.5f32.powf(delta.as_secs_f32() / follow.half_life);
+            center + (wanted - center) * closed
+        } else {
+            wanted
+        };
+        let half_view = viewport / (2.0 * SCALE * camera.zoom);
+        camera.position = Vec2::new(
+            clamp_to_map(center.x, half_view.x, map_size.x),
+            clamp_to_map(center.y, half_view.y, map_size.y),
+        ) * SCALE;
+        camera.update();
+    }
+}
+
+// Zooms every camera in (positive `steps`) or out by whole screen pixels per
+// map pixel, so on top of `SCALE` the map stays pixel sharp.
+pub fn zoom(world: &mut World, steps: i32) {
+    for (_id, camera) in &mut world.query::<&mut Camera>() {
+        let pixel_scale = (camera.zoom * SCALE).round() + steps as f32;
+        camera.zoom = pixel_scale.max(1.0).min(MAX_PIXEL_SCALE) / SCALE;
+        camera.update();
+    }
+}
+
+// Where the camera wants to be along one axis: wherever it is, unless the
+// target is further than `deadzone` from it.
+fn track(center: f32, target: f32, deadzone: f32) -> f32 {
+    if target > center + deadzone {
+        target - deadzone
+    } else if target < center - deadzone {
+        target + deadzone
+    } else {
+        center
+    }
+}
+
+// Keeps a view `half_view` either side of `center` on a map `size` long. A
+// map smaller than the view sits in the middle of it.
+fn clamp_to_map(center: f32, half_view: f32, size: f32) -> f32 {
+    if size <= half_view * 2.0 {
+        size / 2.0
+    } else {
+        center.max(half_view).min(size - half_view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: Vec2<f32> = Vec2 { x: 800.0, y: 600.0 };
+    // 200 by 100 map pixels at SCALE
+    const VIEWPORT: Vec2<f32> = Vec2 {
+        x: 200.0 * SCALE,
+        y: 100.0 * SCALE,
+    };
+
+    fn camera_at(at: Vec2<f32>, follow: CameraFollow) -> (World, hecs::Entity) {
+        let mut world = World::new();
+        let mut camera = Camera::new(VIEWPORT.x, VIEWPORT.y);
+        camera.position = Vec2::new(400.0, 300.0) * SCALE;
+        let entity = world.spawn((camera, Position::new(at), follow));
+        (world, entity)
+    }
+
+    fn center(world: &World, entity: hecs::Entity) -> Vec2<f32> {
+        let camera = world.get::<Camera>(entity).unwrap();
+        camera.position / SCALE
+    }
+
+    #[test]
+    fn stays_inside_the_map() {
+        let (mut world, entity) = camera_at(Vec2::new(10.0, 590.0), CameraFollow::default());
+        follow(&mut world, MAP, VIEWPORT, 0.0, Duration::from_millis(16));
+        assert_eq!(center(&world, entity), Vec2::new(100.0, 550.0));
+    }
+
+    #[test]
+    fn small_maps_are_centred() {
+        assert_eq!(clamp_to_map(30.0, 100.0, 150.0), 75.0);
+    }
+
+    #[test]
+    fn deadzone_and_smoothing() {
+        let follow_slowly = CameraFollow {
+            half_life: 0.5,
+            deadzone: (20.0, 20.0),
+        };
+        // inside the deadzone nothing moves
+        let (mut world, entity) = camera_at(Vec2::new(410.0, 290.0), follow_slowly);
+        follow(&mut world, MAP, VIEWPORT, 0.0, Duration::from_millis(500));
+        assert_eq!(center(&world, entity), Vec2::new(400.0, 300.0));
+        // past it, one half-life closes half the gap to the deadzone's edge
+        let (mut world, entity) = camera_at(Vec2::new(460.0, 300.0), follow_slowly);
+        follow(&mut world, MAP, VIEWPORT, 0.0, Duration::from_millis(500));
+        assert!((center(&world, entity).x - 420.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn zoom_keeps_to_whole_pixels() {
+        let (mut world, entity) = camera_at(Vec2::new(400.0, 300.0), CameraFollow::default());
+        zoom(&mut world, 1);
+        assert_eq!(world.get::<Camera>(entity).unwrap().zoom * SCALE, 3.0);
+        zoom(&mut world, -10);
+        assert_eq!(world.get::<Camera>(entity).unwrap().zoom * SCALE, 1.0);
+    }
+}
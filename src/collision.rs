@@ -0,0 +1,315 @@
+use std::f32::consts::PI;
+
+use nalgebra::base::Vector2;
+use nalgebra::geometry::Point2;
+use ncollide2d::shape::{Ball, ConvexPolygon, Cuboid, Polyline, ShapeHandle};
+use tetra::math::Vec2;
+use tiled::ObjectShape;
+
+use crate::tile::TileTransform;
+
+const ELLIPSE_SEGMENTS: usize = 16;
+
+// A shape and its offset from the centre of the tile it belongs to.
+pub type ShapePart = (Vector2<f32>, ShapeHandle<f32>);
+
+// Turns an object from a tile's collision editor into physics shapes. Flips are
+// applied to the points themselves so mirrored polygons come out right.
+pub fn object_shapes(
+    obj: &tiled::Object,
+    transform: &TileTransform,
+    half_tile: Vec2<f32>,
+) -> Vec<ShapePart> {
+    let origin = Vec2::new(obj.x, obj.y);
+    let (sin, cos) = obj.rotation.to_radians().sin_cos();
+    // Tiled rotates objects around their x/y
+    let place = |point: Vec2<f32>| {
+        let rotated = Vec2::new(point.x * cos - point.y * sin, point.x * sin + point.y * cos);
+        transform.apply(origin + rotated - half_tile)
+    };
+    match &obj.shape {
+        ObjectShape::Rect { width, height } if obj.rotation.abs() < f32::EPSILON => {
+            let size = transform.apply_size(Vec2::new(*width, *height));
+            let center = place(Vec2::new(width / 2.0, height / 2.0));
+            let shape = Cuboid::new(Vector2::new(size.x / 2.0, size.y / 2.0));
+            vec![(to_vector(center), ShapeHandle::new(shape))]
+        }
+        ObjectShape::Rect { width, height } => {
+            let corners = [
+                Vec2::new(0.0, 0.0),
+                Vec2::new(*width, 0.0),
+                Vec2::new(*width, *height),
+                Vec2::new(0.0, *height),
+            ];
+            let points: Vec<_> = corners.iter().map(|&corner| place(corner)).collect();
+            convex_part(&points).into_iter().collect()
+        }
+        ObjectShape::Ellipse { width, height } => {
+            let radii = Vec2::new(width / 2.0, height / 2.0);
+            if (width - height).abs() < 0.01 {
+                let center = place(radii);
+                return vec![(to_vector(center), ShapeHandle::new(Ball::new(radii.x)))];
+            }
+            let points: Vec<_> = (0..ELLIPSE_SEGMENTS)
+                .map(|i| {
+                    let angle = i as f32 / ELLIPSE_SEGMENTS as f32 * 2.0 * PI;
+                    place(radii + Vec2::new(angle.cos() * radii.x, angle.sin() * radii.y))
+                })
+                .collect();
+            convex_part(&points).into_iter().collect()
+        }
+        ObjectShape::Polygon { points } => {
+            let points: Vec<_> = points
+                .iter()
+                .map(|&(x, y)| place(Vec2::new(x, y)))
+                .collect();
+            polygon_parts(&points)
+        }
+        ObjectShape::Polyline { points } => {
+            if points.len() < 2 {
+                return Vec::new();
+            }
+            let points: Vec<_> = points
+                .iter()
+                .map(|&(x, y)| to_point(place(Vec2::new(x, y))))
+                .collect();
+            vec![(
+                Vector2::zeros(),
+                ShapeHandle::new(Polyline::new(points, None)),
+            )]
+        }
+        ObjectShape::Point(_, _) => Vec::new(),
+    }
+}
+
+// Convex polygons are used as is, concave ones are split into triangles.
+pub fn polygon_parts(points: &[Vec2<f32>]) -> Vec<ShapePart> {
+    if points.len() < 3 {
+        return Vec::new();
+    }
+    if is_convex(points) {
+        return convex_part(points).into_iter().collect();
+    }
+    triangulate(points)
+        .iter()
+        .filter_map(|triangle| convex_part(triangle))
+        .collect()
+}
+
+fn convex_part(points: &[Vec2<f32>]) -> Option<ShapePart> {
+    let points: Vec<_> = points.iter().map(|&point| to_point(point)).collect();
+    ConvexPolygon::try_from_points(&points)
+        .map(|polygon| (Vector2::zeros(), ShapeHandle::new(polygon)))
+}
+
+fn cross(a: Vec2<f32>, b: Vec2<f32>, c: Vec2<f32>) -> f32 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+fn signed_area(points: &[Vec2<f32>]) -> f32 {
+    let n = points.len();
+    (0..n)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % n]);
+            a.x * b.y - b.x * a.y
+        })
+        .sum::<f32>()
+        / 2.0
+}
+
+pub fn is_convex(points: &[Vec2<f32>]) -> bool {
+    let n = points.len();
+    let mut winding = None;
+    for i in 0..n {
+        let turn = cross(points[i], points[(i + 1) % n], points[(i + 2) % n]);
+        if turn.abs() < 1e-4 {
+            continue;
+        }
+        match winding {
+            None => winding = Some(turn > 0.0),
+            Some(positive) if positive != (turn > 0.0) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+fn in_triangle(p: Vec2<f32>, a: Vec2<f32>, b: Vec2<f32>, c: Vec2<f32>) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+// Ear clipping. Degenerate input just yields fewer triangles.
+pub fn triangulate(points: &[Vec2<f32>]) -> Vec<[Vec2<f32>; 3]> {
+    let mut indices: Vec<usize> = (0..points.len()).collect();
+    if signed_area(points) < 0.0 {
+        indices.reverse();
+    }
+    let mut triangles = Vec::new();
+    while indices.len() > 3 {
+        let n = indices.len();
+        let corners = |i: usize| {
+            (
+                points[indices[(i + n - 1) % n]],
+                points[indices[i]],
+                points[indices[(i + 1) % n]],
+            )
+        };
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = corners(i);
+            cross(a, b, c) > 0.0
+                && indices.iter().all(|&j| {
+                    let p = points[j];
+                    p == a || p == b || p == c || !in_triangle(p, a, b, c)
+                })
+        });
+        match ear {
+            Some(i) => {
+                let (a, b, c) = corners(i);
+                triangles.push([a, b, c]);
+                indices.remove(i);
+            }
+            None => break,
+        }
+    }
+    if indices.len() == 3 {
+        triangles.push([points[indices[0]], points[indices[1]], points[indices[2]]]);
+    }
+    triangles
+}
+
+// A run of grid cells, in cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Greedy meshing: covers the solid cells with as few rectangles as it cheaply
+// can, growing each one right and then down. Every solid cell ends up in
+// exactly one rectangle.
+pub fn merge_cells(solid: &[Vec<bool>]) -> Vec<CellRect> {
+    let mut taken: Vec<Vec<bool>> = solid.iter().map(|row| vec![false; row.len()]).collect();
+    let free =
+        |taken: &Vec<Vec<bool>>, x: usize, y: usize| solid[y].get(x) == Some(&true) && !taken[y][x];
+    let mut rects = Vec::new();
+    for y in 0..solid.len() {
+        for x in 0..solid[y].len() {
+            if !free(&taken, x, y) {
+                continue;
+            }
+            let mut width = 1;
+            while free(&taken, x + width, y) {
+                width += 1;
+            }
+            let mut height = 1;
+            while y + height < solid.len() && (x..x + width).all(|x| free(&taken, x, y + height)) {
+                height += 1;
+            }
+            for row in &mut taken[y..y + height] {
+                for cell in &mut row[x..x + width] {
+                    *cell = true;
+                }
+            }
+            rects.push(CellRect {
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+    }
+    rects
+}
+
+fn to_vector(point: Vec2<f32>) -> Vector2<f32> {
+    Vector2::new(point.x, point.y)
+}
+
+fn to_point(point: Vec2<f32>) -> Point2<f32> {
+    Point2::new(point.x, point.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn l_shape() -> Vec<Vec2<f32>> {
+        vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(2.0, 0.0),
+            Vec2::new(2.0, 1.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(1.0, 2.0),
+            Vec2::new(0.0, 2.0),
+        ]
+    }
+
+    #[test]
+    fn square_is_convex() {
+        let square = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert!(is_convex(&square));
+        assert_eq!(polygon_parts(&square).len(), 1);
+    }
+
+    #[test]
+    fn concave_polygon_is_split() {
+        assert!(!is_convex(&l_shape()));
+        let triangles = triangulate(&l_shape());
+        assert_eq!(triangles.len(), 4);
+        let area: f32 = triangles.iter().map(|t| signed_area(&t[..]).abs()).sum();
+        assert!((area - 3.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn winding_does_not_matter() {
+        let mut reversed = l_shape();
+        reversed.reverse();
+        assert_eq!(triangulate(&reversed).len(), 4);
+    }
+
+    fn grid(rows: &[&str]) -> Vec<Vec<bool>> {
+        rows.iter()
+            .map(|row| row.chars().map(|c| c == '#').collect())
+            .collect()
+    }
+
+    fn covered(rects: &[CellRect], x: usize, y: usize) -> usize {
+        rects
+            .iter()
+            .filter(|r| x >= r.x && x < r.x + r.width && y >= r.y && y < r.y + r.height)
+            .count()
+    }
+
+    #[test]
+    fn solid_block_is_one_rect() {
+        let rects = merge_cells(&grid(&["###", "###"]));
+        assert_eq!(
+            rects,
+            vec![CellRect {
+                x: 0,
+                y: 0,
+                width: 3,
+                height: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn merged_cells_cover_exactly_the_solid_ones() {
+        let solid = grid(&["##..#", "##.##", "....#", "#####"]);
+        let rects = merge_cells(&solid);
+        assert!(rects.len() < 13);
+        for (y, row) in solid.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                assert_eq!(covered(&rects, x, y), if cell { 1 } else { 0 });
+            }
+        }
+    }
+}
@@ -1,33 +1,76 @@
-use hecs::World;
+use hecs::Entity;
 
-use nphysics2d::force_generator::DefaultForceGeneratorSet;
-use nphysics2d::joint::DefaultJointConstraintSet;
-
-use nphysics2d::object::{DefaultBodyHandle, DefaultBodySet, DefaultColliderSet};
-use nphysics2d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld};
+use nphysics2d::object::{DefaultBodyHandle, DefaultColliderHandle};
 
+use rand::rngs::StdRng;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-use std::time::Duration;
-use tetra::graphics;
-
-use tetra::graphics::{animation::Animation, DrawParams, Rectangle, Texture};
-use tetra::Context;
+use std::time::{Duration, Instant};
+use tetra::graphics::text::Font;
+use tetra::graphics::{Rectangle, Texture};
 
 use tetra::math::Vec2;
 
-use tiled::Layer;
-
-use crate::SCALE;
+use crate::bindings::{Bindings, Rebinding};
+use crate::player::InputState;
+use crate::replay::Recorder;
+use crate::simulation::Simulation;
+use crate::tile::TileAnimation;
 pub struct SpawnBounds {
     pub x: (f32, f32),
     pub y: (f32, f32),
 }
+impl SpawnBounds {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x.0 && x <= self.x.1 && y >= self.y.0 && y <= self.y.1
+    }
+}
+// Touching it loads `map` (relative to the current map) and puts the player on
+// the object named `spawn`.
+pub struct Warp {
+    pub bounds: SpawnBounds,
+    pub map: String,
+    pub spawn: Option<String>,
+}
+// A sensor area from a Tiled `Trigger` object.
+pub struct Trigger {
+    pub name: String,
+    pub properties: tiled::Properties,
+    pub colliders: Vec<DefaultColliderHandle>,
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriggerPhase {
+    Enter,
+    Exit,
+}
+// `other` is the entity that walked in or out, if it has one.
+#[derive(Clone, Debug)]
+pub struct TriggerEvent {
+    pub phase: TriggerPhase,
+    pub trigger: Entity,
+    pub name: String,
+    pub properties: tiled::Properties,
+    pub other: Option<Entity>,
+}
+// The static body that map colliders hang off.
+pub struct Wall(pub DefaultBodyHandle);
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContactPhase {
+    Started,
+    Stopped,
+}
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    pub phase: ContactPhase,
+    pub a: Entity,
+    pub b: Entity,
+}
 pub struct LastDirection(pub Direction);
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Character(pub usize, pub usize);
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum NPCState {
     Up,
     Down,
@@ -36,9 +79,7 @@ pub enum NPCState {
     Idle,
 }
 impl NPCState {
-    pub fn random() -> NPCState {
-        let mut rng = rand::thread_rng();
-
+    pub fn random(rng: &mut StdRng) -> NPCState {
         let state = rng.gen_range(0, 30);
         match state {
             0 => NPCState::Up,
@@ -48,9 +89,7 @@ impl NPCState {
             _ => NPCState::Idle,
         }
     }
-    pub fn random_move() -> NPCState {
-        let mut rng = rand::thread_rng();
-
+    pub fn random_move(rng: &mut StdRng) -> NPCState {
         let state = rng.gen_range(0, 4);
         match state {
             0 => NPCState::Up,
@@ -61,16 +100,24 @@ impl NPCState {
     }
 }
 pub struct GameState {
-    pub world: World,
-    pub sprite_map: HashMap<u32, Sprite>,
-    pub layers: Vec<Layer>,
+    pub sim: Simulation,
     pub texture_map: HashMap<String, Texture>,
-    pub mechanical_world: DefaultMechanicalWorld<f32>,
-    pub geometrical_world: DefaultGeometricalWorld<f32>,
-    pub body_set: DefaultBodySet<f32>,
-    pub collider_set: DefaultColliderSet<f32>,
-    pub constraint_set: DefaultJointConstraintSet<f32>,
-    pub force_gen_set: DefaultForceGeneratorSet<f32>,
+    // drives tile animations, shared so every instance of a tile stays in step
+    pub clock: Duration,
+    // frame time not yet simulated
+    pub accumulator: Duration,
+    // key presses from frames that ran no tick
+    pub pending: InputState,
+    // per-tick input to use instead of the keyboard
+    pub replay: Option<std::vec::IntoIter<InputState>>,
+    pub recorder: Option<Recorder>,
+    pub bindings: Bindings,
+    // waiting for keys to bind, with the game stopped
+    pub rebinding: Option<Rebinding>,
+    // drawn over the game, and when it comes down
+    pub message: Option<(String, Instant)>,
+    pub font: Font,
+    pub paused: bool,
     pub characters: HashMap<usize, Texture>,
     pub npcs: HashMap<usize, Texture>,
 }
@@ -82,135 +129,179 @@ pub struct Sprite {
     pub pos: Vec2<f32>,
     pub texture: String,
     pub collision_objects: Option<Vec<tiled::Object>>,
-    //animation: Option<Animation>,
+    pub animation: Option<TileAnimation>,
 }
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+impl Sprite {
+    // Tiled anchors tiles at the bottom-left of their cell, so tiles bigger than
+    // the map grid stick out upwards and to the right.
+    pub fn top_left(&self, x: usize, y: usize, tile_size: Vec2<f32>) -> Vec2<f32> {
+        Vec2::new(
+            x as f32 * tile_size.x,
+            (y + 1) as f32 * tile_size.y - self.height,
+        )
+    }
+    pub fn clip(&self, clock: Duration) -> Rectangle {
+        self.animation
+            .as_ref()
+            .and_then(|animation| animation.frame(clock))
+            .unwrap_or(self.rect)
+    }
+}
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Direction {
     Up,
     Down,
     Left,
     Right,
 }
-#[derive(Clone, PartialEq)]
-pub enum DrawType {
-    Character,
-    Tile,
-    NPC,
+// Where an entity is in world space (unscaled), now and as of the tick before,
+// so drawing can interpolate between the two. Characters are synced from their
+// body every tick; tiles stay put at their top-left corner.
+#[derive(Clone, Copy, Debug)]
+pub struct Position {
+    pub current: Vec2<f32>,
+    pub previous: Vec2<f32>,
 }
-#[derive(Clone)]
-pub struct CharacterDrawData {
-    pub entity_animation: EntityAnimation,
-    pub handle: DefaultBodyHandle,
-    pub animation_data: AnimationData,
-    pub character: Character,
-    pub colliding: bool
+impl Position {
+    pub fn new(at: Vec2<f32>) -> Position {
+        Position {
+            current: at,
+            previous: at,
+        }
+    }
+    // `blend` of the way from the previous tick to the latest one.
+    pub fn interpolated(&self, blend: f32) -> Vec2<f32> {
+        self.previous + (self.current - self.previous) * blend
+    }
 }
-#[derive(Clone)]
-pub struct TileDrawData {
-    pub pos: Vec2<f32>,
-    pub sprite: Sprite,
-    pub rotation: f32,
+pub struct BodyHandle(pub DefaultBodyHandle);
+// Which sprite sheets a skin indexes into.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SheetSet {
+    Player,
+    Npc,
+}
+pub struct CharacterSkin {
+    pub character: Character,
+    pub sheets: SheetSet,
 }
+// A character's animation state machine. Movement picks `state` every tick
+// (see `animation::animate`); an action such as Interact plays through once
+// on top of it. Gameplay code only sets `direction` to say which way the
+// character faces.
 #[derive(Clone)]
-pub struct Draw {
-    pub y: f32,
-    pub draw_type: DrawType,
-    pub player: Option<CharacterDrawData>,
-    pub tile: Option<TileDrawData>,
+pub struct Animator {
+    pub direction: Direction,
+    pub state: AnimState,
+    pub action: Option<AnimState>,
+    pub animations: AnimationData,
 }
-
-impl Draw {
-    pub fn draw(
-        &self,
-        ctx: &mut Context,
-        texture_map: &HashMap<String, Texture>,
-        characters: (&HashMap<usize, Texture>, &HashMap<usize, Texture>),
-        body_set: &DefaultBodySet<f32>,
-    ) {
-        match self.draw_type {
-            DrawType::Tile => {
-                let tile = self.tile.as_ref().unwrap();
-                let texture = texture_map.get(&tile.sprite.texture).unwrap();
-                let position = tile.pos;
-                graphics::draw(
-                    ctx,
-                    texture,
-                    DrawParams::new()
-                        .position(Vec2::new(position.x + 16.0, position.y - 16.0))
-                        .origin(Vec2::new(8.0, 8.0))
-                        .scale(Vec2::new(SCALE, SCALE))
-                        .clip(tile.sprite.rect)
-                        .rotation(tile.rotation.to_radians()),
-                );
-            }
-            DrawType::Character => {
-                let player = self.player.as_ref().unwrap().clone();
-                let entity_anim = player.entity_animation;
-                let anim_data = player.animation_data;
-                let character = player.character;
-                let handle = player.handle;
-                let anim = match entity_anim.direction {
-                    Direction::Up => &anim_data.up,
-                    Direction::Down => &anim_data.down,
-                    Direction::Left => &anim_data.left,
-                    Direction::Right => &anim_data.right,
-                };
-                let mut animation = Animation::new(
-                    characters.0.get(&character.0).unwrap().clone(),
-                    anim.frames.clone(),
-                    anim.frame_duration,
-                );
-                animation.set_current_frame_index(anim.frame_index);
-                let body = body_set.rigid_body(handle).unwrap();
-                let pos = Vec2::new(
-                    body.position().translation.vector.x * 2.0,
-                    body.position().translation.vector.y * 2.0,
-                );
-                graphics::draw(
-                    ctx,
-                    &animation,
-                    DrawParams::new()
-                        .position(pos)
-                        .origin(Vec2::new(9.5, 27.0))
-                        .scale(Vec2::new(SCALE, SCALE)),
-                );
+impl Animator {
+    pub fn new(animations: AnimationData) -> Animator {
+        Animator {
+            direction: Direction::Down,
+            state: AnimState::Idle,
+            action: None,
+            animations,
+        }
+    }
+    // Switches state, starting the new state's animation from its first frame.
+    pub fn set_state(&mut self, state: AnimState) {
+        if state != self.state {
+            self.state = state;
+            if self.action.is_none() {
+                self.restart();
             }
-            DrawType::NPC => {
-                let player = self.player.as_ref().unwrap().clone();
-                let entity_anim = player.entity_animation;
-                let anim_data = player.animation_data;
-                let character = player.character;
-                let handle = player.handle;
-                let anim = match entity_anim.direction {
-                    Direction::Up => &anim_data.up,
-                    Direction::Down => &anim_data.down,
-                    Direction::Left => &anim_data.left,
-                    Direction::Right => &anim_data.right,
-                };
-                let mut animation = Animation::new(
-                    characters.1.get(&character.0).unwrap().clone(),
-                    anim.frames.clone(),
-                    anim.frame_duration,
-                );
-                animation.set_current_frame_index(anim.frame_index);
-                let body = body_set.rigid_body(handle).unwrap();
-                let pos = Vec2::new(
-                    body.position().translation.vector.x * 2.0,
-                    body.position().translation.vector.y * 2.0,
-                );
-                graphics::draw(
-                    ctx,
-                    &animation,
-                    DrawParams::new()
-                        .position(pos)
-                        .origin(Vec2::new(9.5, 27.0))
-                        .scale(Vec2::new(SCALE, SCALE)),
-                );
+        }
+    }
+    // Plays `action` once, then goes back to the movement state. A sheet with
+    // no animation for the action just carries on with the movement state.
+    pub fn perform(&mut self, action: AnimState) {
+        if self.animations.get(action, self.direction).is_some() {
+            self.action = Some(action);
+            self.restart();
+        }
+    }
+    pub fn advance(&mut self, delta_time: Duration) {
+        let finished = match self.playing() {
+            Some((state, direction)) => self
+                .animations
+                .get_mut(state, direction)
+                .map_or(true, |anim| anim.advance(delta_time)),
+            // holding a still frame; an action the sheet can't show at all
+            // is over straight away
+            None => true,
+        };
+        if finished && self.action.take().is_some() {
+            self.restart();
+        }
+    }
+    // The frame to draw: the playing animation's, or the first frame of the
+    // walk cycle to stand still on if the sheet has nothing for the state.
+    pub fn current_frame(&self) -> Option<Rectangle> {
+        match self.playing() {
+            Some((state, direction)) => self
+                .animations
+                .get(state, direction)
+                .map(Anim::current_frame),
+            None => self
+                .animations
+                .get(AnimState::Walk, self.direction)
+                .map(|anim| anim.frames[0]),
+        }
+    }
+    // The animation that stands in for the current action or state, after
+    // falling back through what the sheet has.
+    fn playing(&self) -> Option<(AnimState, Direction)> {
+        let mut state = self.action.unwrap_or(self.state);
+        while self.animations.get(state, self.direction).is_none() {
+            state = state.fallback()?;
+        }
+        Some((state, self.direction))
+    }
+    fn restart(&mut self) {
+        if let Some((state, direction)) = self.playing() {
+            if let Some(anim) = self.animations.get_mut(state, direction) {
+                anim.restart();
             }
-            _ => unimplemented!("Invalid Draw Type"),
         }
     }
 }
+// Sort key for drawing: higher values are further down the screen and drawn
+// on top.
+pub struct RenderLayer(pub f32);
+// Set when an NPC bumps into something, cleared once it has turned away.
+pub struct Collided(pub bool);
+// Walking speed in map pixels per second.
+pub struct Speed(pub f32);
+// How quickly a character gets up to speed and slows to a stop, in map pixels
+// per second squared. Characters without one start and stop dead.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Acceleration {
+    pub accelerate: f32,
+    pub decelerate: f32,
+}
+// How a camera follows the entity it's on, in map pixels and seconds. The
+// defaults keep the entity dead centre.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraFollow {
+    // how long the camera takes to close half the distance; 0 keeps up
+    pub half_life: f32,
+    // how far the entity can get from the middle of the screen, each way,
+    // before the camera moves
+    pub deadzone: (f32, f32),
+}
+// The prefab an entity was spawned from, so a save can spawn it again.
+pub struct PrefabName(pub String);
+// How an NPC decides where to go.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ai {
+    // picks a random direction every so often, and a new one on bumping
+    // into something
+    Wander,
+    // stays where it is unless pushed
+    Idle,
+}
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub enum AnimationKey {
     PlayerUp,
@@ -218,45 +309,147 @@ pub enum AnimationKey {
     PlayerLeft,
     PlayerRight,
 }
-#[derive(Copy, Clone)]
-pub struct EntityAnimation {
-    pub direction: Direction,
-}
-
 #[derive(Clone, Debug)]
 pub struct Anim {
     pub frames: Vec<Rectangle>,
-    pub frame_duration: Duration,
+    // how long each frame is shown for, one per frame
+    pub durations: Vec<Duration>,
     pub time_elapsed: Duration,
     pub frame_index: usize,
 }
-
 impl Anim {
     pub fn new(frames: &[Rectangle], frame_duration: Duration) -> Anim {
+        Anim::timed(frames.iter().map(|&frame| (frame, frame_duration)).collect())
+    }
+    pub fn timed(frames: Vec<(Rectangle, Duration)>) -> Anim {
         Anim {
-            frames: frames.to_vec(),
-            frame_duration,
+            frames: frames.iter().map(|&(frame, _)| frame).collect(),
+            durations: frames.iter().map(|&(_, duration)| duration).collect(),
             time_elapsed: Duration::from_secs_f64(0.0),
             frame_index: 0,
         }
     }
-    pub fn advance(&mut self, delta_time: Duration) {
+    pub fn current_frame(&self) -> Rectangle {
+        self.frames[self.frame_index]
+    }
+    pub fn restart(&mut self) {
+        self.time_elapsed = Duration::from_secs_f64(0.0);
+        self.frame_index = 0;
+    }
+    // Returns true when the animation wraps back to its first frame.
+    pub fn advance(&mut self, delta_time: Duration) -> bool {
         self.time_elapsed += delta_time;
-        if self.time_elapsed > self.frame_duration {
+        if self.time_elapsed > self.durations[self.frame_index] {
             self.time_elapsed = Duration::from_secs_f64(0.0);
             self.frame_index += 1;
             if self.frame_index >= self.frames.len() {
                 self.frame_index = 0;
+                return true;
             }
         }
+        false
     }
 }
-#[derive(Clone)]
+// What a character is doing, which picks the animation along with its
+// direction. Named in sheets as `<state>_<direction>`, e.g. `walk_down`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum AnimState {
+    Idle,
+    Walk,
+    Run,
+    Interact,
+}
+impl AnimState {
+    pub fn from_name(name: &str) -> Option<AnimState> {
+        match name {
+            "idle" => Some(AnimState::Idle),
+            "walk" => Some(AnimState::Walk),
+            "run" => Some(AnimState::Run),
+            "interact" => Some(AnimState::Interact),
+            _ => None,
+        }
+    }
+    // What to show instead when a sheet has no animation for this state.
+    // Idle with nothing to show holds a still frame; actions aren't stood in
+    // for, see `Animator::perform`.
+    pub fn fallback(self) -> Option<AnimState> {
+        match self {
+            AnimState::Idle => None,
+            AnimState::Walk => None,
+            AnimState::Run => Some(AnimState::Walk),
+            AnimState::Interact => None,
+        }
+    }
+}
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+        Direction::Up,
+    ];
+    pub fn from_name(name: &str) -> Option<Direction> {
+        match name {
+            "up" => Some(Direction::Up),
+            "down" => Some(Direction::Down),
+            "left" => Some(Direction::Left),
+            "right" => Some(Direction::Right),
+            _ => None,
+        }
+    }
+    // Which way to face while heading along `heading`, or None when standing
+    // still. Mostly along one axis faces that way. On a diagonal a character
+    // keeps facing whichever of the two ways it already did, and otherwise
+    // turns sideways, so sprites don't flip back and forth.
+    pub fn facing(heading: Vec2<f32>, current: Direction) -> Option<Direction> {
+        let horizontal = if heading.x > 0.0 {
+            Some(Direction::Right)
+        } else if heading.x < 0.0 {
+            Some(Direction::Left)
+        } else {
+            None
+        };
+        let vertical = if heading.y > 0.0 {
+            Some(Direction::Down)
+        } else if heading.y < 0.0 {
+            Some(Direction::Up)
+        } else {
+            None
+        };
+        let (x, y) = (heading.x.abs(), heading.y.abs());
+        match (horizontal, vertical) {
+            (Some(horizontal), Some(_)) if x > y * DIAGONAL => Some(horizontal),
+            (Some(_), Some(vertical)) if y > x * DIAGONAL => Some(vertical),
+            (Some(_), Some(vertical)) if current == vertical => Some(vertical),
+            (horizontal, vertical) => horizontal.or(vertical),
+        }
+    }
+}
+// How many times bigger one axis of a heading has to be than the other for it
+// not to count as a diagonal.
+const DIAGONAL: f32 = 2.0;
+// Every animation of one sheet, by state and direction.
+#[derive(Clone, Debug, Default)]
 pub struct AnimationData {
-    pub left: Anim,
-    pub right: Anim,
-    pub up: Anim,
-    pub down: Anim,
+    anims: HashMap<(AnimState, Direction), Anim>,
+}
+impl AnimationData {
+    // Splits a name like `idle_left` into its state and direction.
+    pub fn key(name: &str) -> Option<(AnimState, Direction)> {
+        let mut parts = name.splitn(2, '_');
+        let state = AnimState::from_name(parts.next()?)?;
+        let direction = Direction::from_name(parts.next()?)?;
+        Some((state, direction))
+    }
+    pub fn insert(&mut self, state: AnimState, direction: Direction, anim: Anim) {
+        self.anims.insert((state, direction), anim);
+    }
+    pub fn get(&self, state: AnimState, direction: Direction) -> Option<&Anim> {
+        self.anims.get(&(state, direction))
+    }
+    pub fn get_mut(&mut self, state: AnimState, direction: Direction) -> Option<&mut Anim> {
+        self.anims.get_mut(&(state, direction))
+    }
 }
 
 pub struct Player;
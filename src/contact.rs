@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use ncollide2d::pipeline::ContactEvent;
+use nphysics2d::object::{DefaultBodyHandle, DefaultColliderHandle, DefaultColliderSet};
+use nphysics2d::world::DefaultGeometricalWorld;
+
+use crate::components::{BodyHandle, Contact, ContactPhase, Trigger, Wall};
+
+// Which entity owns each collider. Physics events only carry collider handles,
+// so this is rebuilt whenever a level is loaded.
+pub fn collider_entities(
+    world: &World,
+    colliders: &DefaultColliderSet<f32>,
+) -> HashMap<DefaultColliderHandle, Entity> {
+    let mut bodies: HashMap<DefaultBodyHandle, Entity> = HashMap::new();
+    for (entity, handle) in &mut world.query::<&BodyHandle>() {
+        bodies.insert(handle.0, entity);
+    }
+    for (entity, wall) in &mut world.query::<&Wall>() {
+        bodies.insert(wall.0, entity);
+    }
+
+    let mut entities = HashMap::new();
+    for (handle, collider) in colliders.iter() {
+        if let Some(&entity) = bodies.get(&collider.body()) {
+            entities.insert(handle, entity);
+        }
+    }
+    for (entity, trigger) in &mut world.query::<&Trigger>() {
+        for &handle in &trigger.colliders {
+            entities.insert(handle, entity);
+        }
+    }
+    entities
+}
+
+// Contacts that started or stopped during the last physics step, between
+// colliders that belong to an entity.
+pub fn contact_events(
+    geometrical_world: &DefaultGeometricalWorld<f32>,
+    entities: &HashMap<DefaultColliderHandle, Entity>,
+) -> Vec<Contact> {
+    geometrical_world
+        .contact_events()
+        .iter()
+        .filter_map(|event| contact(event, entities))
+        .collect()
+}
+
+fn contact(
+    event: &ContactEvent<DefaultColliderHandle>,
+    entities: &HashMap<DefaultColliderHandle, Entity>,
+) -> Option<Contact> {
+    let (phase, collider1, collider2) = match *event {
+        ContactEvent::Started(collider1, collider2) => {
+            (ContactPhase::Started, collider1, collider2)
+        }
+        ContactEvent::Stopped(collider1, collider2) => {
+            (ContactPhase::Stopped, collider1, collider2)
+        }
+    };
+    Some(Contact {
+        phase,
+        a: *entities.get(&collider1)?,
+        b: *entities.get(&collider2)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ncollide2d::shape::{Ball, ShapeHandle};
+    use nphysics2d::object::{BodyPartHandle, ColliderDesc, DefaultBodySet, RigidBodyDesc};
+
+    fn ball(
+        bodies: &mut DefaultBodySet<f32>,
+        colliders: &mut DefaultColliderSet<f32>,
+    ) -> (DefaultBodyHandle, DefaultColliderHandle) {
+        let body = bodies.insert(RigidBodyDesc::new().build());
+        let collider = ColliderDesc::new(ShapeHandle::new(Ball::new(1.0)));
+        (body, colliders.insert(collider.build(BodyPartHandle(body, 0))))
+    }
+
+    #[test]
+    fn events_name_both_entities() {
+        let mut world = World::new();
+        let mut bodies = DefaultBodySet::new();
+        let mut colliders = DefaultColliderSet::new();
+        let (body, character) = ball(&mut bodies, &mut colliders);
+        let character_entity = world.spawn((BodyHandle(body),));
+        let (wall_body, wall) = ball(&mut bodies, &mut colliders);
+        let wall_entity = world.spawn((Wall(wall_body),));
+        // a body nothing in the world owns
+        let (_, stray) = ball(&mut bodies, &mut colliders);
+        let entities = collider_entities(&world, &colliders);
+
+        let started = contact(&ContactEvent::Started(character, wall), &entities).unwrap();
+        assert_eq!(started.phase, ContactPhase::Started);
+        assert_eq!((started.a, started.b), (character_entity, wall_entity));
+        let stopped = contact(&ContactEvent::Stopped(wall, character), &entities).unwrap();
+        assert_eq!(stopped.phase, ContactPhase::Stopped);
+        assert_eq!((stopped.a, stopped.b), (wall_entity, character_entity));
+        assert!(contact(&ContactEvent::Started(character, stray), &entities).is_none());
+        assert!(contact(&ContactEvent::Stopped(stray, wall), &entities).is_none());
+    }
+}
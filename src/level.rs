@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use hecs::World;
+use nalgebra::base::Vector2;
+use nphysics2d::force_generator::DefaultForceGeneratorSet;
+use nphysics2d::joint::DefaultJointConstraintSet;
+use nphysics2d::object::{DefaultBodySet, DefaultColliderSet};
+use nphysics2d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tetra::graphics::Camera;
+use tetra::Context;
+use tiled::PropertyValue::IntValue;
+
+use crate::components::{Animator, CharacterSkin, GameState, Player, Wall, Warp};
+use crate::contact::collider_entities;
+use crate::map::{check_gids, LoadedMap};
+use crate::prefab::{check_prefabs, Spawner};
+use crate::simulation::Simulation;
+use crate::world_gen::{create_map_bounds, create_physics_world, spawn};
+
+impl GameState {
+    // Loads the textures for `loaded` before handing it to the simulation, so a
+    // missing image leaves the current level running.
+    pub fn load_level(
+        &mut self,
+        ctx: &mut Context,
+        loaded: LoadedMap,
+        spawn_point: Option<&str>,
+    ) -> tetra::Result {
+        let texture_map = loaded.load_textures(ctx)?;
+        self.sim.load_level(loaded, spawn_point)?;
+        self.texture_map = texture_map;
+        Ok(())
+    }
+
+    // Catches the textures up with a map the simulation warped to.
+    pub fn reload_textures(&mut self, ctx: &mut Context) -> tetra::Result {
+        self.texture_map = LoadedMap::reload(&self.sim.map_path)?.load_textures(ctx)?;
+        Ok(())
+    }
+}
+
+impl Simulation {
+    // Replaces the current map, entities and physics with those of `loaded`.
+    // Everything that can fail happens before the old level is torn down.
+    pub fn load_level(&mut self, loaded: LoadedMap, spawn_point: Option<&str>) -> tetra::Result {
+        self.build_level(loaded, spawn_point, true)
+    }
+
+    // Like `load_level`, but NPC spawns can be left empty for a save to fill.
+    pub fn build_level(
+        &mut self,
+        loaded: LoadedMap,
+        spawn_point: Option<&str>,
+        with_npcs: bool,
+    ) -> tetra::Result {
+        let sprite_map = loaded.sprites();
+        check_gids(&loaded, &sprite_map)?;
+        check_prefabs(&loaded, &self.prefabs)?;
+        let tile_size = loaded.tile_size();
+        // a map can pin its own seed unless the command line already did
+        if let (None, Some(IntValue(seed))) = (self.seed, loaded.map.properties.get("seed")) {
+            self.rng = StdRng::seed_from_u64(*seed as u64);
+        }
+
+        // the player keeps their skin between maps, and the camera its zoom
+        let character = self
+            .world
+            .query::<(&Player, &CharacterSkin)>()
+            .iter()
+            .next()
+            .map(|(_, (_, skin))| skin.character);
+        let zoom = self
+            .world
+            .query::<&Camera>()
+            .iter()
+            .next()
+            .map(|(_, camera)| camera.zoom);
+
+        let mut world = World::new();
+        let mut bodies = DefaultBodySet::new();
+        let mut colliders = DefaultColliderSet::new();
+        let layers = loaded.map.layers.clone();
+        if let Some(ground) = layers.first() {
+            let bounds = create_map_bounds(ground, tile_size, &mut colliders, &mut bodies);
+            world.spawn((Wall(bounds),));
+        }
+        let mut spawner = Spawner {
+            colliders: &mut colliders,
+            bodies: &mut bodies,
+            world: &mut world,
+            sheets: &self.sheets,
+            viewport: self.viewport,
+        };
+        spawn(
+            &mut spawner,
+            &self.prefabs,
+            &loaded.map,
+            spawn_point,
+            &mut self.rng,
+            with_npcs,
+        );
+        if let Some(character) = character {
+            for (_id, (_player, skin, animator)) in
+                &mut world.query::<(&Player, &mut CharacterSkin, &mut Animator)>()
+            {
+                skin.character = character;
+                // the prefab spawned with its own sheet's animations
+                animator.animations = self.sheets.get(skin).animations.clone();
+            }
+        }
+        if let Some(zoom) = zoom {
+            for (_id, camera) in &mut world.query::<&mut Camera>() {
+                camera.zoom = zoom;
+                camera.update();
+            }
+        }
+        let tiles = create_physics_world(
+            &layers,
+            tile_size,
+            &sprite_map,
+            &mut colliders,
+            &mut bodies,
+        );
+        world.spawn((Wall(tiles),));
+
+        self.collider_entities = collider_entities(&world, &colliders);
+        self.world = world;
+        self.body_set = bodies;
+        self.collider_set = colliders;
+        self.mechanical_world = DefaultMechanicalWorld::new(Vector2::new(0.0, 0.0));
+        self.geometrical_world = DefaultGeometricalWorld::new();
+        self.constraint_set = DefaultJointConstraintSet::new();
+        self.force_gen_set = DefaultForceGeneratorSet::new();
+        self.sprite_map = sprite_map;
+        self.layers = layers;
+        self.tile_size = tile_size;
+        self.map_size = loaded.pixel_size();
+        self.map_path = loaded.path;
+        self.warp_armed = false;
+        self.contacts.clear();
+        self.trigger_events.clear();
+        Ok(())
+    }
+
+    // Warp targets are relative to the map that contains the warp.
+    pub fn resolve_map(&self, target: &str) -> PathBuf {
+        match self.map_path.parent() {
+            Some(dir) => dir.join(target),
+            None => PathBuf::from(target),
+        }
+    }
+
+    // Loads the map behind the warp the player just walked onto, if any. A map
+    // that won't load leaves the player where they are.
+    pub fn follow_warp(&mut self) {
+        if let Some((target, spawn_point)) = self.touched_warp() {
+            let path = self.resolve_map(&target);
+            let result = LoadedMap::load(Some(&path))
+                .and_then(|map| self.load_level(map, spawn_point.as_deref()));
+            match result {
+                Ok(()) => self.warped = true,
+                Err(err) => eprintln!("can't warp to {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    // Returns the warp the player just walked onto, if any.
+    pub fn touched_warp(&mut self) -> Option<(String, Option<String>)> {
+        let position = self.player_position()?;
+        let warp = self
+            .world
+            .query::<&Warp>()
+            .iter()
+            .map(|(_, warp)| warp)
+            .find(|warp| warp.bounds.contains(position.x, position.y))
+            .map(|warp| (warp.map.clone(), warp.spawn.clone()));
+        let armed = self.warp_armed;
+        self.warp_armed = warp.is_none();
+        if armed {
+            warp
+        } else {
+            None
+        }
+    }
+}
@@ -8,59 +8,91 @@ mod player;
 use player::*;
 mod npc;
 use npc::*;
+mod map;
+use map::*;
+mod tile;
+use tile::*;
+mod animation;
+mod aseprite;
+mod bindings;
+use bindings::*;
+mod camera;
+mod collision;
+mod level;
+mod trigger;
+mod contact;
+mod simulation;
+use simulation::*;
+mod replay;
+use replay::*;
+mod render;
+mod prefab;
+use prefab::*;
+mod save;
+mod sheet;
+use sheet::*;
+mod schedule;
+use schedule::*;
+
+use std::iter::Peekable;
+use std::time::Instant;
+use tetra::graphics::text::Font;
+use tetra::TetraError;
 
 const WINDOW_WIDTH: f32 = 1600.0;
 const WINDOW_HEIGHT: f32 = 900.0;
 
 pub const SCALE: f32 = 2.0;
 
-const CHAR_HEIGHT: f32 = 32.0;
-const CHAR_WIDTH: f32 = 19.0;
-
-const ANIM_SPEED: f64 = 0.2;
-
-const TILESETS: &[(&str, &[u8])] = &[
-    (
-        "terrain_2",
-        include_bytes!("../resources/map/tilesets/terrain_2.png"),
-    ),
-    (
-        "outdoors",
-        include_bytes!("../resources/map/tilesets/outside.png"),
-    ),
-    (
-        "chest-sheet",
-        include_bytes!("../resources/map/tilesets/chest-sheet.png"),
-    ),
-    (
-        "castle",
-        include_bytes!("../resources/map/tilesets/castle.png"),
-    ),
-];
+// Player, NPC and physics updates per second, independent of the frame rate.
+const TICK_RATE: f64 = 60.0;
+const MAX_TICKS_PER_FRAME: u32 = 5;
+const HEADLESS_TICKS: u64 = 10_000;
+
+// For the rebinding prompt and messages.
+const FONT: &[u8] = include_bytes!("../resources/fonts/DejaVuSansMono.ttf");
+const FONT_SIZE: f32 = 18.0;
+// How long a message stays on screen.
+const MESSAGE_TIME: Duration = Duration::from_secs(4);
+
+// A sheet's frame layout lives next to it in `<name>.ron`. Sheets drawn in
+// Aseprite can also bring its JSON export, `<name>.json`, with `aseprite`.
+macro_rules! sheet {
+    ($name:literal) => {
+        SheetSource {
+            name: $name,
+            image: include_bytes!(concat!("../resources/", $name, ".png")),
+            descriptor: include_str!(concat!("../resources/", $name, ".ron")),
+            aseprite: None,
+        }
+    };
+    ($name:literal, aseprite) => {
+        SheetSource {
+            name: $name,
+            image: include_bytes!(concat!("../resources/", $name, ".png")),
+            descriptor: include_str!(concat!("../resources/", $name, ".ron")),
+            aseprite: Some(include_str!(concat!("../resources/", $name, ".json"))),
+        }
+    };
+}
 
-const PLAYER_SHEETS: &[(&usize, &[u8])] = &[
-    (&0, include_bytes!("../resources/Wizard-Sheet.png")),
-    (&1, include_bytes!("../resources/Viking-Sheet.png")),
-    (&2, include_bytes!("../resources/Fire-Man-Sheet.png")),
-    (&3, include_bytes!("../resources/Red-Hair-Sheet.png"))
+// Indexed by `Character`'s first field.
+const PLAYER_SHEETS: &[SheetSource] = &[
+    sheet!("Wizard-Sheet"),
+    sheet!("Viking-Sheet"),
+    sheet!("Fire-Man-Sheet"),
+    sheet!("Red-Hair-Sheet"),
 ];
 
-const NPC_SHEETS: &[(&usize, &[u8])] = &[
-    (&0, include_bytes!("../resources/Wizard-Sheet.png")),
-    (&1, include_bytes!("../resources/Viking-Sheet.png")),
-    (&2, include_bytes!("../resources/Fire-Man-Sheet.png")),
-    (&3, include_bytes!("../resources/NPC01-Sheet.png")),
-    (&4, include_bytes!("../resources/NPC02-Sheet.png")),
-    (&5, include_bytes!("../resources/Red-Hair-Sheet.png"))
+const NPC_SHEETS: &[SheetSource] = &[
+    sheet!("Wizard-Sheet"),
+    sheet!("Viking-Sheet"),
+    sheet!("Fire-Man-Sheet"),
+    sheet!("NPC01-Sheet"),
+    sheet!("NPC02-Sheet"),
+    sheet!("Red-Hair-Sheet"),
 ];
 
-// x width for char = 75
-// y height for char = 144
-
-// down = x: 0.0 y: 0.0
-// right = x: 0.0, y: 36.0
-// left = x: 0.0, y: 72.0
-// up = x: 0.0, y: 108.
 fn get_layer_size(lyr: tiled::Layer) -> Vec2<u32> {
     let mut size_y = 0;
     let mut size_x = 0;
@@ -78,6 +110,8 @@ fn get_layer_size(lyr: tiled::Layer) -> Vec2<u32> {
 
 fn draw_layer(
     lyr: tiled::Layer,
+    tile_size: Vec2<f32>,
+    clock: Duration,
     texture_map: &HashMap<String, Texture>,
     sprite_map: &HashMap<u32, Sprite>,
     ctx: &mut Context,
@@ -89,308 +123,278 @@ fn draw_layer(
             }
 
             let gid = tile.gid;
-            let sprite = sprite_map.get(&gid).unwrap();
-            let mut origin = Vec2::new(8.0, 8.0);
-            if sprite.width == 16.0 && sprite.height == 16.0 {
-                origin.x = 8.0;
-                origin.y = 8.0;
-            }
-
-            let texture = texture_map.get(&sprite.texture).unwrap();
-            let mut rotation: f32 = 0.0;
-            if tile.flip_h {
-                rotation += 180.0;
-            }
-            if tile.flip_d {
-                rotation -= 90.0;
-            }
-            if tile.flip_v {
-                rotation += 0.0;
-            }
+            let sprite = match sprite_map.get(&gid) {
+                Some(sprite) => sprite,
+                None => continue,
+            };
+            let origin = Vec2::new(sprite.width / 2.0, sprite.height / 2.0);
+            let position = (sprite.top_left(x, y, tile_size) + origin) * SCALE;
+
+            let texture = match texture_map.get(&sprite.texture) {
+                Some(texture) => texture,
+                None => continue,
+            };
+            let transform = TileTransform::from_tile(&tile);
 
             graphics::draw(
                 ctx,
                 texture,
                 DrawParams::new()
-                    .position(Vec2::new(
-                        (x as f32 * 32.0) + 16.0,
-                        (y as f32 * 32.0) + 16.0,
-                    ))
+                    .position(position)
                     .origin(origin)
-                    .scale(Vec2::new(SCALE, SCALE))
-                    .clip(sprite.rect)
-                    .rotation(rotation.to_radians()),
+                    .scale(transform.scale() * SCALE)
+                    .clip(sprite.clip(clock))
+                    .rotation(transform.rotation()),
             );
         }
     }
 }
 
-fn spawn_ecs_tiles(lyr: &tiled::Layer, world: &mut World, sprite_map: &HashMap<u32, Sprite>) {
-    for (y, row) in lyr.tiles.iter().enumerate().clone() {
-        for (x, &tile) in row.iter().enumerate() {
-            if tile.gid == 0 {
-                continue;
-            }
-
-            let gid = tile.gid;
-
-            let mut rotation: f32 = 0.0;
-            if tile.flip_h {
-                rotation += 180.0;
-            }
-            if tile.flip_d {
-                rotation -= 90.0;
-            }
-            if tile.flip_v {
-                rotation -= 0.0;
-            }
-
-            let pos = Vec2::new((x as f32 * 32.0), (y as f32 * 32.0) + 32.0);
-            world.spawn((Draw {
-                y: pos.y,
-                draw_type: DrawType::Tile,
-                player: None,
-                tile: Some(TileDrawData {
-                    pos,
-                    sprite: sprite_map.get(&gid).unwrap().clone(),
-                    rotation,
-                }),
-            },));
-        }
-    }
-}
-
-fn handle_contact(world: &DefaultGeometricalWorld<f32>, event: &ContactEvent<DefaultBodyHandle>, ecs_world: &mut World){
-    if let &ContactEvent::Started(collider1, collider2) = event {
-        for (_id, ( draw, _npc)) in &mut ecs_world.query::<(&mut Draw, &NPC)>() {
-            if draw.player.as_mut().unwrap().handle == collider1 || draw.player.as_mut().unwrap().handle == collider2{
-                draw.player.as_mut().unwrap().colliding = true;
-            }
-        }
-    }
+pub fn new_simulation(viewport: Vec2<f32>, seed: Option<u64>) -> tetra::Result<Simulation> {
+    let sheets = Sheets::load(PLAYER_SHEETS, NPC_SHEETS)?;
+    let prefabs = Prefabs::load()?;
+    prefabs.check_sheets(&sheets)?;
+    Ok(Simulation::new(
+        sheets,
+        prefabs,
+        viewport,
+        Duration::from_secs_f64(1.0 / TICK_RATE),
+        seed,
+    ))
 }
 
 impl GameState {
-    fn new(ctx: &mut Context) -> tetra::Result<GameState> {
-        let mut world = World::new();
-        let mut file_to_texture = HashMap::new();
-        for (k, v) in TILESETS {
-            file_to_texture
-                .entry(k.to_string())
-                .or_insert(Texture::from_file_data(ctx, v)?);
-        }
-        
+    fn new(ctx: &mut Context, options: Options) -> tetra::Result<GameState> {
+        let Options {
+            map_path,
+            seed,
+            record,
+            replay,
+            disabled,
+            ..
+        } = options;
+        let loaded = LoadedMap::load(map_path.as_deref())?;
 
     
         let mut character_map = HashMap::new();
         let mut npc_map = HashMap::new();
-        for (k,v) in PLAYER_SHEETS {
-            character_map.insert(**k, Texture::from_file_data(ctx, v)?);
+        for (index, sheet) in PLAYER_SHEETS.iter().enumerate() {
+            character_map.insert(index, Texture::from_file_data(ctx, sheet.image)?);
         }
-        for (k,v) in NPC_SHEETS {
-            npc_map.insert(**k, Texture::from_file_data(ctx, v)?);
+        for (index, sheet) in NPC_SHEETS.iter().enumerate() {
+            npc_map.insert(index, Texture::from_file_data(ctx, sheet.image)?);
         }
 
-        let anim_left: Vec<_> = Rectangle::row(0.0, 32.0, CHAR_WIDTH, CHAR_HEIGHT)
-            .take(3)
-            .collect();
-        let anim_right: Vec<_> = Rectangle::row(0.0, 64.0, CHAR_WIDTH, CHAR_HEIGHT)
-            .take(3)
-            .collect();
-        let anim_up: Vec<_> = Rectangle::row(0.0, 96.0, CHAR_WIDTH, CHAR_HEIGHT)
-            .take(3)
-            .collect();
-        let anim_down: Vec<_> = Rectangle::row(0.0, 0.0, CHAR_WIDTH, CHAR_HEIGHT)
-            .take(3)
-            .collect();
-
-        let anim_data = AnimationData {
-            left: Anim::new(&anim_left, Duration::from_secs_f64(ANIM_SPEED)),
-            right: Anim::new(&anim_right, Duration::from_secs_f64(ANIM_SPEED)),
-            up: Anim::new(&anim_up, Duration::from_secs_f64(ANIM_SPEED)),
-            down: Anim::new(&anim_down, Duration::from_secs_f64(ANIM_SPEED)),
+        let viewport = Vec2::new(
+            window::get_width(ctx) as f32,
+            window::get_height(ctx) as f32,
+        );
+        // a recording has to pin the seed down to be replayable
+        let seed = match (&replay, &record) {
+            (Some(replay), _) => Some(replay.seed),
+            (None, Some(_)) => Some(seed.unwrap_or_else(rand::random)),
+            (None, None) => seed,
+        };
+        let recorder = match &record {
+            Some(path) => Some(Recorder::create(path, seed.unwrap(), map_path.as_deref())?),
+            None => None,
+        };
+        let mut sim = new_simulation(viewport, seed)?;
+        disable_systems(&mut sim, &disabled);
+        let mut state = GameState {
+            sim,
+            pending: InputState::default(),
+            replay: replay.map(|replay| replay.inputs.into_iter()),
+            recorder,
+            bindings: Bindings::load()?,
+            rebinding: None,
+            message: None,
+            font: Font::from_vector_file_data(ctx, FONT, FONT_SIZE)?,
+            paused: false,
+            characters: character_map,
+            npcs: npc_map,
+            clock: Duration::from_secs(0),
+            accumulator: Duration::from_secs(0),
+            texture_map: HashMap::new(),
         };
+        state.load_level(ctx, loaded, None)?;
+        Ok(state)
+    }
+
+    // How far between the last tick and the next one this frame falls.
+    fn blend(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.sim.tick.as_secs_f32()
+    }
 
-        let tiled_data = parse(&include_bytes!("../resources/map/map5.tmx")[..]).unwrap();
-        //fs::write("map.ron", format!("{:#?}", &tiled_data.clone())).unwrap();
-
-        let map = &tiled_data.clone();
-        //fs::write("bar.json", format!("{:#?}", tiled_data)).unwrap();
-        let tilesets = tiled_data.tilesets;
-        let mut tile_sprites: HashMap<u32, Sprite> = HashMap::new();
-        let mut gid = tilesets[0].first_gid as u32;
-        for x in 0..tilesets.len() {
-            let map_tileset = tilesets[x].clone();
-            let tile_width = map_tileset.tile_width as i32;
-            let tile_height = map_tileset.tile_height as i32;
-            let tileset_width = &map_tileset.images[0].width;
-            let tileset_height = &map_tileset.images[0].height;
-            let tileset_sprite_columns = tileset_width / tile_width as i32;
-            let tileset_sprite_rows = tileset_height / tile_height as i32;
-            let mut object_map: HashMap<u32, Vec<tiled::Object>> = HashMap::new();
-            let mut id_to_rect: HashMap<u32, Rectangle> = HashMap::new();
-            let mut anim_map: HashMap<u32, Animation> = HashMap::new();
-            for tile in map_tileset.tiles {
-                if tile.objectgroup.is_some() {
-                    object_map.insert(tile.id, tile.objectgroup.unwrap().objects);
+    // Puts `message` on screen for a few seconds, replacing any before it.
+    fn show(&mut self, message: String) {
+        self.message = Some((message, Instant::now() + MESSAGE_TIME));
+    }
+
+    // Passes the first key pressed this frame on to the rebinding, and saves
+    // the bindings when it finishes having changed any. The prompt is drawn
+    // from the rebinding itself.
+    fn rebind(&mut self, ctx: &Context) {
+        let key = match input::get_keys_pressed(ctx).next() {
+            Some(&key) => key,
+            None => return,
+        };
+        let rebinding = self.rebinding.as_mut().unwrap();
+        match rebinding.press(&mut self.bindings, key) {
+            RebindStep::Waiting => {}
+            RebindStep::Bound(action) => {
+                let clashes: Vec<_> = self
+                    .bindings
+                    .clashes(action)
+                    .iter()
+                    .map(|(key, other)| format!("{:?} is bound to {:?} as well", key, other))
+                    .collect();
+                if !clashes.is_empty() {
+                    self.show(clashes.join("\n"));
                 }
-                // if tile.animation.is_some() && id_to_rect.is_empty() {
-                //     let mut id = 0;
-                //     for x in 0..tileset_sprite_rows {
-                //         for y in 0..tileset_sprite_columns {
-                //             let sprite_w = tile_width as f32;
-                //             let sprite_h = tile_height as f32;
-                //             let pos_x = (x * tile_width) as f32;
-                //             let pos_y = (y * tile_height) as f32;
-                //             Rectangle::new(pos_y, pos_x, sprite_w, sprite_h)
-                //             tile_sprites.entry(gid).or_insert(sprite);
-                //             id += 1;
-                //         }
-                //     }
-                // }
             }
-            let mut id = 0;
-            for x in 0..tileset_sprite_rows {
-                for y in 0..tileset_sprite_columns {
-                    let sprite_w = tile_width as f32;
-                    let sprite_h = tile_height as f32;
-                    let pos_x = (x * tile_width) as f32;
-                    let pos_y = (y * tile_height) as f32;
-                    let objects = object_map.remove(&id).clone();
-                    let sprite = Sprite {
-                        width: sprite_w,
-                        height: sprite_h,
-                        rect: Rectangle::new(pos_y, pos_x, sprite_w, sprite_h),
-                        pos: Vec2::new(pos_x, pos_y),
-                        texture: map_tileset.name.clone(),
-                        collision_objects: objects,
-                    };
-
-                    tile_sprites.entry(gid).or_insert(sprite);
-                    gid += 1;
-                    id += 1;
+            RebindStep::Finished => {
+                let changed = rebinding.changed;
+                self.rebinding = None;
+                if !changed {
+                    return;
+                }
+                match self.bindings.write(Path::new(BINDINGS_PATH)) {
+                    Ok(()) => self.show(format!("bindings saved to {}", BINDINGS_PATH)),
+                    Err(err) => self.show(format!("can't save bindings: {}", err)),
                 }
             }
         }
-
-        let geometrical_world: DefaultGeometricalWorld<f32> = DefaultGeometricalWorld::new();
-        let mechanical_world: DefaultMechanicalWorld<f32> =
-            DefaultMechanicalWorld::new(Vector2::new(0.0, 0.0));
-        let mut bodies = DefaultBodySet::new();
-        let mut colliders = DefaultColliderSet::new();
-        let joint_constraints: DefaultJointConstraintSet<f32> = DefaultJointConstraintSet::new();
-        let force_generators: DefaultForceGeneratorSet<f32> = DefaultForceGeneratorSet::new();
-        
-        let layers = tiled_data.layers;
-        create_map_bounds(&layers[0], &mut colliders, &mut bodies);
-
-        
-
-
-        spawn(&mut colliders, &mut bodies, &mut world, (&(npc_map.len() - 1), &(character_map.len() - 1)), &anim_data, map, ctx );
-
-        let top_layers = &layers[1..];
-
-        for layer in top_layers {
-            spawn_ecs_tiles(layer, &mut world, &tile_sprites);
-        }
-
-        
-
-        create_physics_world(&layers, &tile_sprites, &mut colliders, &mut bodies);
-
-        Ok(GameState {
-            characters: character_map,
-            npcs: npc_map,
-            world,
-            sprite_map: tile_sprites,
-            layers: layers,
-            texture_map: file_to_texture,
-            mechanical_world: mechanical_world,
-            geometrical_world: geometrical_world,
-            body_set: bodies,
-            collider_set: colliders,
-            force_gen_set: force_generators,
-            constraint_set: joint_constraints,
-        })
     }
 }
 
 impl State for GameState {
     fn draw(&mut self, ctx: &mut Context) -> tetra::Result {
         //&self.texture.set_current_frame_index(1);
-        for (_id, camera) in self.world.query::<&Camera>().iter().take(1) {
+        for (_id, camera) in self.sim.world.query::<&Camera>().iter().take(1) {
             graphics::set_transform_matrix(ctx, camera.as_matrix());
         }
         graphics::clear(ctx, Color::rgb(0.0, 0.0, 0.0));
 
-        let mut layers = self.layers.clone();
-        let bg_layer: tiled::Layer = layers.remove(0);
-        let bg_layer2: tiled::Layer = layers.remove(0);
-        draw_layer(bg_layer.clone(), &self.texture_map, &self.sprite_map, ctx);
-        draw_layer(bg_layer2.clone(), &self.texture_map, &self.sprite_map, ctx);
-        let mut render_vec: Vec<_> = self
-            .world
-            .query::<&Draw>()
-            .iter()
-            .map(|(_, d)| d.clone())
-            .collect();
-        render_vec.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap());
-        for draw in render_vec {
-            if draw.draw_type == DrawType::Character || draw.draw_type == DrawType::NPC {
-                draw.draw(ctx, &self.texture_map, (&self.characters, &self.npcs), &self.body_set);
-            }
+        // the bottom layers go under the characters and the rest over them;
+        // a map may have fewer background layers than usual, or none
+        let (background, overlay) = self
+            .sim
+            .layers
+            .split_at(self.sim.layers.len().min(render::BACKGROUND_LAYERS));
+        for layer in background {
+            draw_layer(
+                layer.clone(),
+                self.sim.tile_size,
+                self.clock,
+                &self.texture_map,
+                &self.sim.sprite_map,
+                ctx,
+            );
         }
-        for layer in layers {
-            draw_layer(layer, &self.texture_map, &self.sprite_map, ctx);
+        let blend = self.blend();
+        render::draw_characters(ctx, self, blend);
+        for layer in overlay {
+            draw_layer(
+                layer.clone(),
+                self.sim.tile_size,
+                self.clock,
+                &self.texture_map,
+                &self.sim.sprite_map,
+                ctx,
+            );
         }
+        render::draw_overlay(ctx, self);
 
         Ok(())
     }
 
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        player_update(&mut self.body_set, ctx, &mut self.world);
-        npc_update(&mut self.body_set, &mut self.world, ctx);
-        for contact in self.geometrical_world.contact_events() {
-            handle_contact(&self.geometrical_world, &contact, &mut self.world)
+        if self.rebinding.is_some() {
+            self.rebind(ctx);
+            return Ok(());
         }
-        self.mechanical_world.step(
-            &mut self.geometrical_world,
-            &mut self.body_set,
-            &mut self.collider_set,
-            &mut self.constraint_set,
-            &mut self.force_gen_set,
-        );
+        if self.bindings.is_pressed(ctx, Action::Quit) {
+            window::quit(ctx);
+            return Ok(());
+        }
+        if self.bindings.is_pressed(ctx, Action::Rebind) {
+            self.rebinding = Some(Rebinding::start());
+            return Ok(());
+        }
+        if self.bindings.is_pressed(ctx, Action::Pause) {
+            self.paused = !self.paused;
+        }
+        if self.bindings.is_pressed(ctx, Action::Timings) {
+            print_timings(&mut self.sim.schedule);
+        }
+        if self.paused {
+            return Ok(());
+        }
+        let delta_time = tetra::time::get_delta_time(ctx);
+        self.clock += delta_time;
+        if self.bindings.is_pressed(ctx, Action::Quicksave) {
+            if let Err(err) = self.quicksave() {
+                eprintln!("quicksave failed: {}", err);
+            }
+        }
+        if self.bindings.is_pressed(ctx, Action::Quickload) {
+            if let Err(err) = self.quickload(ctx) {
+                eprintln!("quickload failed: {}", err);
+            }
+        }
+        if self.bindings.is_pressed(ctx, Action::ZoomIn) {
+            camera::zoom(&mut self.sim.world, 1);
+        }
+        if self.bindings.is_pressed(ctx, Action::ZoomOut) {
+            camera::zoom(&mut self.sim.world, -1);
+        }
+        let mut input = InputState::from_bindings(ctx, &self.bindings);
+        input.carry_presses(&self.pending);
+
+        // a slow frame runs a few ticks to catch up, but not so many that
+        // the next frame is even slower
+        let tick = self.sim.tick;
+        self.accumulator = (self.accumulator + delta_time).min(tick * MAX_TICKS_PER_FRAME);
+        while self.accumulator >= tick {
+            self.accumulator -= tick;
+            let tick_input = match self.replay.as_mut() {
+                Some(replay) => replay.next().unwrap_or_default(),
+                None => input,
+            };
+            self.sim.step(&tick_input);
+            input.clear_presses();
+            if let Some(recorder) = self.recorder.as_mut() {
+                if let Err(err) = recorder.record(&tick_input) {
+                    eprintln!("stopped recording: {}", err);
+                    self.recorder = None;
+                }
+            }
 
-        for (_id, (camera, _player, draw)) in
-            &mut self.world.query::<(&mut Camera, &Player, &Draw)>()
-        {
-            let handle = draw.player.as_ref().unwrap().handle;
-            let player_body = self.body_set.rigid_body_mut(handle).unwrap();
-            player_body.set_linear_velocity(Vector2::new(0.0, 0.0));
-            camera.position = Vec2::new(
-                player_body.position().translation.vector.x * 2.0,
-                player_body.position().translation.vector.y * 2.0,
-            );
-            camera.update();
+            if self.sim.warped {
+                self.sim.warped = false;
+                if let Err(err) = self.reload_textures(ctx) {
+                    eprintln!("can't load textures for {}: {}", self.sim.map_path.display(), err);
+                }
+            }
         }
-        for (_id, draw) in &mut self.world.query::<(&mut Draw)>() {
-            if draw.draw_type == DrawType::Character || draw.draw_type == DrawType::NPC {
-                let entity = draw.player.as_ref().unwrap();
-                let handle = entity.handle;
-                let y = self
-                    .body_set
-                    .rigid_body(handle)
-                    .unwrap()
-                    .position()
-                    .translation
-                    .y;
-
-                draw.y = y;
+
+        self.pending = input;
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(err) = recorder.flush() {
+                eprintln!("stopped recording: {}", err);
+                self.recorder = None;
             }
         }
-        // for(_id, (_npc, handle)) in &mut self.world.query::<(&NPC, &DefaultBodyHandle)>(){
-        //     let body = self.body_set.rigid_body_mut(*handle).unwrap();
+
+        let frame = Frame {
+            input: &self.pending,
+            blend: self.blend(),
+            delta: delta_time,
+        };
+        self.sim.run_stages(&Stage::FRAME, &frame);
+        // for(_id, (_npc, handle)) in &mut self.sim.world.query::<(&NPC, &DefaultBodyHandle)>(){
+        //     let body = self.sim.body_set.rigid_body_mut(*handle).unwrap();
         //     body.set_linear_velocity(Vector2::new(0.0, 0.0));
         // }
 
@@ -398,20 +402,151 @@ impl State for GameState {
     }
 
     fn event(&mut self, _ctx: &mut Context, event: Event) -> tetra::Result {
-        if let Event::Resized { width, height } = event {
-            for (_id, camera) in self.world.query::<&mut Camera>().iter().take(1) {
-                camera.set_viewport_size(width as f32, height as f32);
-                camera.update();
+        match event {
+            Event::Resized { width, height } => {
+                self.sim.viewport = Vec2::new(width as f32, height as f32);
+                for (_id, camera) in self.sim.world.query::<&mut Camera>().iter().take(1) {
+                    camera.set_viewport_size(width as f32, height as f32);
+                    camera.update();
+                }
+            }
+            Event::MouseWheelMoved { amount } if amount.y != 0 => {
+                camera::zoom(&mut self.sim.world, amount.y.signum());
             }
+            // gamepads already plugged in at startup come through here too
+            Event::GamepadAdded { id } => self.bindings.gamepad_added(id),
+            Event::GamepadRemoved { id } => self.bindings.gamepad_removed(id),
+            _ => {}
         }
         Ok(())
     }
 }
 
+// Runs the simulation for `ticks` ticks with no window, then reports where
+// the player ended up. Input comes from the replay if there is one.
+fn run_headless(options: Options) -> tetra::Result {
+    let viewport = Vec2::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+    let (seed, inputs) = match options.replay {
+        Some(replay) => (Some(replay.seed), replay.inputs),
+        None => (options.seed, Vec::new()),
+    };
+    let ticks = options.ticks.unwrap_or(if inputs.is_empty() {
+        HEADLESS_TICKS
+    } else {
+        inputs.len() as u64
+    });
+    let mut sim = new_simulation(viewport, seed)?;
+    disable_systems(&mut sim, &options.disabled);
+    sim.load_level(LoadedMap::load(options.map_path.as_deref())?, None)?;
+    let mut inputs = inputs.into_iter();
+    for _ in 0..ticks {
+        sim.step(&inputs.next().unwrap_or_default());
+    }
+    match sim.player_position() {
+        Some(position) => println!(
+            "player at {}, {} after {} ticks",
+            position.x, position.y, ticks
+        ),
+        None => println!("no player after {} ticks", ticks),
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Options {
+    map_path: Option<PathBuf>,
+    seed: Option<u64>,
+    headless: bool,
+    ticks: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<Replay>,
+    // systems to switch off, for debugging
+    disabled: Vec<String>,
+}
+
+fn disable_systems(sim: &mut Simulation, names: &[String]) {
+    for name in names {
+        if !sim.schedule.set_enabled(name, false) {
+            eprintln!("no system called {}", name);
+        }
+    }
+}
+
+fn print_timings(schedule: &mut Schedule<Simulation>) {
+    for system in schedule.systems() {
+        println!(
+            "{:?} {}: {:?}{}",
+            system.stage,
+            system.name,
+            system.last_run,
+            if system.enabled { "" } else { " (off)" }
+        );
+    }
+}
+
+// The argument after `flag`, which mustn't be missing or another flag.
+fn flag_value(
+    args: &mut Peekable<impl Iterator<Item = String>>,
+    flag: &str,
+    needs: &str,
+) -> tetra::Result<String> {
+    match args.peek() {
+        Some(value) if !value.starts_with("--") => Ok(args.next().unwrap()),
+        _ => Err(TetraError::PlatformError(format!("{} needs {}", flag, needs))),
+    }
+}
+
+fn parse_args() -> tetra::Result<Options> {
+    let mut options = Options::default();
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => {
+                options.headless = true;
+                options.ticks = args.peek().and_then(|ticks| ticks.parse().ok());
+                if options.ticks.is_some() {
+                    args.next();
+                }
+            }
+            "--seed" => {
+                let seed = flag_value(&mut args, "--seed", "a number")?;
+                let parsed = seed.parse().map_err(|_| {
+                    TetraError::PlatformError(format!("--seed needs a number, not {:?}", seed))
+                })?;
+                options.seed = Some(parsed);
+            }
+            "--record" => {
+                let path = flag_value(&mut args, "--record", "a file to write")?;
+                options.record = Some(PathBuf::from(path));
+            }
+            "--disable" => {
+                let system = flag_value(&mut args, "--disable", "a system name")?;
+                options.disabled.push(system);
+            }
+            "--replay" => {
+                let path = flag_value(&mut args, "--replay", "a file to play")?;
+                options.replay = Some(Replay::load(Path::new(&path))?);
+            }
+            _ => options.map_path = Some(PathBuf::from(arg)),
+        }
+    }
+    // a replay knows which map it was recorded on
+    if let Some(replay) = &options.replay {
+        if options.map_path.is_none() {
+            options.map_path = replay.map.clone();
+        }
+    }
+    Ok(options)
+}
+
 fn main() -> tetra::Result {
+    let options = parse_args()?;
+    if options.headless {
+        return run_headless(options);
+    }
     ContextBuilder::new("Neon", WINDOW_WIDTH as i32, WINDOW_HEIGHT as i32)
         .resizable(true)
-        .quit_on_escape(true)
+        .timestep(Timestep::Variable)
         .build()?
-        .run(GameState::new)
+        .run(|ctx| GameState::new(ctx, options))
 }
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tetra::graphics::{Rectangle, Texture};
+use tetra::math::Vec2;
+use tetra::{Context, TetraError};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::components::Sprite;
+use crate::tile::TileAnimation;
+
+const EMBEDDED_MAP: &[u8] = include_bytes!("../resources/map/map5.tmx");
+const EMBEDDED_MAP_PATH: &str = "resources/map/map5.tmx";
+
+// Used when a tileset image can't be found on disk. Matched against the file
+// name of the image source, or against the tileset name.
+const EMBEDDED_TILESETS: &[(&str, &[u8])] = &[
+    (
+        "terrain_2.png",
+        include_bytes!("../resources/map/tilesets/terrain_2.png"),
+    ),
+    (
+        "outside.png",
+        include_bytes!("../resources/map/tilesets/outside.png"),
+    ),
+    (
+        "chest-sheet.png",
+        include_bytes!("../resources/map/tilesets/chest-sheet.png"),
+    ),
+    (
+        "castle.png",
+        include_bytes!("../resources/map/tilesets/castle.png"),
+    ),
+];
+
+pub struct LoadedMap {
+    pub map: tiled::Map,
+    pub path: PathBuf,
+    // first_gid -> .tsx path for tilesets that live in their own file
+    pub external_tilesets: HashMap<u32, PathBuf>,
+}
+
+impl LoadedMap {
+    // Falls back to the map baked into the binary when no path is given.
+    pub fn load(path: Option<&Path>) -> tetra::Result<LoadedMap> {
+        let (path, data) = match path {
+            Some(path) => {
+                let data = fs::read(path).map_err(|reason| TetraError::FailedToLoadAsset {
+                    reason,
+                    path: path.to_path_buf(),
+                })?;
+                (path.to_path_buf(), data)
+            }
+            None => (PathBuf::from(EMBEDDED_MAP_PATH), EMBEDDED_MAP.to_vec()),
+        };
+        let map = tiled::parse_with_path(&data[..], &path)
+            .map_err(|err| map_error(&path, format!("{:?}", err)))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let external_tilesets = external_tilesets(&String::from_utf8_lossy(&data))
+            .into_iter()
+            .map(|(gid, source)| (gid, dir.join(source)))
+            .collect();
+        Ok(LoadedMap {
+            map,
+            path,
+            external_tilesets,
+        })
+    }
+
+    // Loads a map by the path a previous load recorded, which for the built-in
+    // map need not exist on disk.
+    pub fn reload(path: &Path) -> tetra::Result<LoadedMap> {
+        if path == Path::new(EMBEDDED_MAP_PATH) && !path.exists() {
+            LoadedMap::load(None)
+        } else {
+            LoadedMap::load(Some(path))
+        }
+    }
+
+    // Tiled stores image sources relative to the file the tileset is defined in.
+    pub fn resolve(&self, tileset: &tiled::Tileset, source: &str) -> PathBuf {
+        let file = self
+            .external_tilesets
+            .get(&tileset.first_gid)
+            .unwrap_or(&self.path);
+        file.parent().unwrap_or_else(|| Path::new("")).join(source)
+    }
+
+    pub fn error(&self, msg: String) -> TetraError {
+        map_error(&self.path, msg)
+    }
+
+    // Loads every tileset image, keyed by the resolved image path. Sprites refer
+    // to their texture through the same key.
+    pub fn load_textures(&self, ctx: &mut Context) -> tetra::Result<HashMap<String, Texture>> {
+        let mut textures = HashMap::new();
+        for tileset in &self.map.tilesets {
+            let image = match tileset.images.first() {
+                Some(image) => image,
+                None => {
+                    return Err(self.error(format!("tileset {} has no image", tileset.name)))
+                }
+            };
+            let path = self.resolve(tileset, &image.source);
+            let key = self.texture_key(tileset);
+            if textures.contains_key(&key) {
+                continue;
+            }
+            let texture = if path.exists() {
+                Texture::new(ctx, &path)?
+            } else {
+                match embedded_tileset(tileset, &path) {
+                    Some(data) => Texture::from_file_data(ctx, data)?,
+                    None => {
+                        return Err(self.error(format!(
+                            "missing image {} for tileset {}",
+                            image.source, tileset.name
+                        )))
+                    }
+                }
+            };
+            textures.insert(key, texture);
+        }
+        Ok(textures)
+    }
+
+    // One sprite per tile of every tileset, keyed by gid.
+    pub fn sprites(&self) -> HashMap<u32, Sprite> {
+        let mut tile_sprites: HashMap<u32, Sprite> = HashMap::new();
+        for map_tileset in &self.map.tilesets {
+            let image = match map_tileset.images.first() {
+                Some(image) => image,
+                None => continue,
+            };
+            let texture = self.texture_key(map_tileset);
+            let mut gid = map_tileset.first_gid;
+            let tile_width = map_tileset.tile_width as i32;
+            let tile_height = map_tileset.tile_height as i32;
+            let tileset_sprite_columns = image.width / tile_width;
+            let tileset_sprite_rows = image.height / tile_height;
+            let mut object_map: HashMap<u32, Vec<tiled::Object>> = HashMap::new();
+            let mut anim_map: HashMap<u32, &Vec<tiled::Frame>> = HashMap::new();
+            for tile in &map_tileset.tiles {
+                if let Some(group) = &tile.objectgroup {
+                    object_map.insert(tile.id, group.objects.clone());
+                }
+                if let Some(frames) = &tile.animation {
+                    anim_map.insert(tile.id, frames);
+                }
+            }
+            let mut id = 0;
+            for x in 0..tileset_sprite_rows {
+                for y in 0..tileset_sprite_columns {
+                    let sprite_w = tile_width as f32;
+                    let sprite_h = tile_height as f32;
+                    let pos_x = (x * tile_height) as f32;
+                    let pos_y = (y * tile_width) as f32;
+                    let sprite = Sprite {
+                        width: sprite_w,
+                        height: sprite_h,
+                        rect: Rectangle::new(pos_y, pos_x, sprite_w, sprite_h),
+                        pos: Vec2::new(pos_x, pos_y),
+                        texture: texture.clone(),
+                        collision_objects: object_map.remove(&id),
+                        animation: None,
+                    };
+
+                    tile_sprites.entry(gid).or_insert(sprite);
+                    gid += 1;
+                    id += 1;
+                }
+            }
+            // frames point at other tiles of the same tileset
+            let first_gid = map_tileset.first_gid;
+            for (id, frames) in anim_map {
+                let frames: Vec<_> = frames
+                    .iter()
+                    .filter_map(|frame| {
+                        let rect = tile_sprites.get(&(first_gid + frame.tile_id))?.rect;
+                        Some((rect, Duration::from_millis(frame.duration as u64)))
+                    })
+                    .collect();
+                if let Some(sprite) = tile_sprites.get_mut(&(first_gid + id)) {
+                    sprite.animation = Some(TileAnimation::new(frames));
+                }
+            }
+        }
+        tile_sprites
+    }
+
+    pub fn tile_size(&self) -> Vec2<f32> {
+        Vec2::new(self.map.tile_width as f32, self.map.tile_height as f32)
+    }
+
+    // The whole map, in map pixels.
+    pub fn pixel_size(&self) -> Vec2<f32> {
+        Vec2::new(self.map.width as f32, self.map.height as f32) * self.tile_size()
+    }
+
+    pub fn texture_key(&self, tileset: &tiled::Tileset) -> String {
+        match tileset.images.first() {
+            Some(image) => self
+                .resolve(tileset, &image.source)
+                .to_string_lossy()
+                .into_owned(),
+            None => String::new(),
+        }
+    }
+}
+
+fn embedded_tileset(tileset: &tiled::Tileset, path: &Path) -> Option<&'static [u8]> {
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    EMBEDDED_TILESETS
+        .iter()
+        .find(|(name, _)| {
+            Some(*name) == file_name
+                || Path::new(name).file_stem().and_then(|stem| stem.to_str())
+                    == Some(tileset.name.as_str())
+        })
+        .map(|(_, data)| *data)
+}
+
+// tiled resolves <tileset source="..."> for us but doesn't keep the .tsx path,
+// which we need to find the tileset's image.
+fn external_tilesets(tmx: &str) -> HashMap<u32, String> {
+    let mut sources = HashMap::new();
+    for event in EventReader::from_str(tmx) {
+        let attributes = match event {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) if name.local_name == "tileset" => attributes,
+            Ok(_) => continue,
+            // tiled has already read the map, so it's well formed
+            Err(_) => break,
+        };
+        let attribute = |key: &str| {
+            attributes
+                .iter()
+                .find(|attribute| attribute.name.local_name == key)
+                .map(|attribute| attribute.value.as_str())
+        };
+        if let (Some(gid), Some(source)) = (attribute("firstgid"), attribute("source")) {
+            if let Ok(gid) = gid.parse() {
+                sources.insert(gid, source.to_string());
+            }
+        }
+    }
+    sources
+}
+
+pub fn map_error(path: &Path, msg: String) -> TetraError {
+    TetraError::FailedToLoadAsset {
+        reason: io::Error::new(io::ErrorKind::InvalidData, msg),
+        path: path.to_path_buf(),
+    }
+}
+
+pub fn check_gids(map: &LoadedMap, sprite_map: &HashMap<u32, Sprite>) -> tetra::Result {
+    for layer in &map.map.layers {
+        for row in &layer.tiles {
+            for tile in row {
+                if tile.gid != 0 && !sprite_map.contains_key(&tile.gid) {
+                    return Err(map.error(format!(
+                        "unknown gid {} in layer {}",
+                        tile.gid, layer.name
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,114 +1,202 @@
+use crate::bindings::{Action, Bindings};
 use crate::components::*;
-use hecs::{Entity, World};
+use crate::sheet::Sheets;
+use hecs::World;
 use nalgebra::base::Vector2;
 
-use nalgebra::geometry::{Isometry2};
-use ncollide2d::shape::{Cuboid, ShapeHandle};
-use nphysics2d::object::{
-    BodyPartHandle, BodyStatus, ColliderDesc, DefaultBodySet,
-    DefaultColliderSet, RigidBodyDesc,
-};
-use tetra::graphics::Camera;
-use tetra::input::{self, Key};
+use nphysics2d::object::DefaultBodySet;
+use tetra::math::Vec2;
 
 use tetra::Context;
 
-pub const PLAYER_SPEED: f32 = 1.5 * 75.0;
+use std::time::Duration;
 
-pub fn new_player(
-    ctx: &mut Context,
-    world: &mut World,
-    char_count: usize,
-    bodies: &mut DefaultBodySet<f32>,
-    colliders: &mut DefaultColliderSet<f32>,
-    anims: AnimationData,
-    pos: &Vector2<f32>,
-) -> tetra::Result<Entity> {
-    let camera = Camera::with_window_size(ctx);
-
-    let player_shape = ShapeHandle::new(Cuboid::new(Vector2::new(5.25, 5.0)));
-
-    let player_pos = Isometry2::new(*pos, nalgebra::zero());
-
-    let player_body = RigidBodyDesc::new()
-        .position(player_pos)
-        .gravity_enabled(false)
-        .status(BodyStatus::Dynamic)
-        .mass(1.2)
-        .build();
-    let y = player_body.position().translation.y.clone();
-    let player_handle = bodies.insert(player_body);
+// What the player is asking for this tick. Read from the bound keys and
+// gamepad buttons when there is a window, filled in by hand when running
+// headless.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputState {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub previous_character: bool,
+    pub next_character: bool,
+    pub interact: bool,
+    // analog movement, already past the deadzone; zero leaves it to the keys
+    pub stick: Vec2<f32>,
+}
 
-    let player_collider = ColliderDesc::new(player_shape).build(BodyPartHandle(player_handle, 0));
+impl InputState {
+    pub fn from_bindings(ctx: &Context, bindings: &Bindings) -> InputState {
+        InputState {
+            up: bindings.is_down(ctx, Action::MoveUp),
+            down: bindings.is_down(ctx, Action::MoveDown),
+            left: bindings.is_down(ctx, Action::MoveLeft),
+            right: bindings.is_down(ctx, Action::MoveRight),
+            previous_character: bindings.is_pressed(ctx, Action::PreviousSkin),
+            next_character: bindings.is_pressed(ctx, Action::NextSkin),
+            interact: bindings.is_pressed(ctx, Action::Interact),
+            stick: bindings.stick(ctx),
+        }
+    }
 
-    colliders.insert(player_collider);
+    // Key presses only last a frame, and a frame may run no ticks at all, so
+    // they are carried over until a tick has seen them.
+    pub fn carry_presses(&mut self, earlier: &InputState) {
+        self.previous_character |= earlier.previous_character;
+        self.next_character |= earlier.next_character;
+        self.interact |= earlier.interact;
+    }
 
-    let draw = Draw {
-        draw_type: DrawType::Character,
-        y,
-        tile: None,
-        player: Some(CharacterDrawData {
-            animation_data: anims,
-            entity_animation: EntityAnimation {
-                direction: Direction::Down,
-            },
-            character: Character(0, char_count),
-            handle: player_handle,
-            colliding: false
-        }),
-    };
+    // The held direction as a vector at most one long; opposite keys cancel
+    // out and diagonals are no faster than straight lines. A stick that's
+    // pushed wins over the keys, and only part way is slower.
+    pub fn heading(&self) -> Vec2<f32> {
+        if self.stick != Vec2::zero() {
+            return self.stick;
+        }
+        let axis = |negative: bool, positive: bool| match (negative, positive) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        };
+        let heading = Vec2::new(axis(self.left, self.right), axis(self.up, self.down));
+        if heading == Vec2::zero() {
+            heading
+        } else {
+            heading.normalized()
+        }
+    }
 
-    Ok(world.spawn((Player, draw, camera)))
+    pub fn clear_presses(&mut self) {
+        self.previous_character = false;
+        self.next_character = false;
+        self.interact = false;
+    }
 }
 
-pub fn player_update(body_set: &mut DefaultBodySet<f32>, ctx: &mut Context, world: &mut World) {
-    let delta_time = tetra::time::get_delta_time(ctx);
-
-    for (_id, (_camera, draw, _player)) in &mut world.query::<(
-        &mut Camera,
-        &mut Draw,
-        &Player,
-        
-    )>() {
-        let player = draw.player.as_mut().unwrap();
-        let handle = player.handle;
-        let player_body = body_set.rigid_body_mut(handle).unwrap();
-        
-       
-        if input::is_key_pressed(ctx, Key::LeftBracket) {
-            if player.character.0 > 0 {
-                player.character.0 = player.character.0 - 1;
+pub fn switch_character(world: &mut World, input: &InputState, sheets: &Sheets) {
+    for (_id, (skin, animator, _player)) in
+        &mut world.query::<(&mut CharacterSkin, &mut Animator, &Player)>()
+    {
+        let before = skin.character;
+        if input.previous_character {
+            if skin.character.0 > 0 {
+                skin.character.0 = skin.character.0 - 1;
             } else {
-                player.character.0 = player.character.1;
+                skin.character.0 = skin.character.1;
             }
         }
-        if input::is_key_pressed(ctx, Key::RightBracket) {
-            if player.character.0 < player.character.1 {
-                player.character.0 = player.character.0 + 1;
+        if input.next_character {
+            if skin.character.0 < skin.character.1 {
+                skin.character.0 = skin.character.0 + 1;
             } else {
-                player.character.0 = 0;
+                skin.character.0 = 0;
             }
         }
-        if input::is_key_down(ctx, Key::W) {
-            player_body.set_linear_velocity(Vector2::new(0.0, -PLAYER_SPEED));
-            player.entity_animation.direction = Direction::Up;
-            player.animation_data.up.advance(delta_time);
+        // the new sheet may be laid out differently
+        if skin.character != before {
+            animator.animations = sheets.get(skin).animations.clone();
         }
-        if input::is_key_down(ctx, Key::S) {
-            player_body.set_linear_velocity(Vector2::new(0.0, PLAYER_SPEED));
-            player.entity_animation.direction = Direction::Down;
-            player.animation_data.down.advance(delta_time);
-        }
-        if input::is_key_down(ctx, Key::D) {
-            player_body.set_linear_velocity(Vector2::new(PLAYER_SPEED, 0.0));
-            player.entity_animation.direction = Direction::Right;
-            player.animation_data.right.advance(delta_time);
+    }
+}
+
+// Steers the player towards the held direction at its prefab's speed, the
+// same speed on a diagonal as straight on.
+pub fn player_update(
+    body_set: &mut DefaultBodySet<f32>,
+    world: &mut World,
+    input: &InputState,
+    tick: Duration,
+) {
+    let heading = input.heading();
+    for (_id, (body, animator, _player, speed, acceleration)) in &mut world.query::<(
+        &BodyHandle,
+        &mut Animator,
+        &Player,
+        &Speed,
+        Option<&Acceleration>,
+    )>() {
+        let player_body = body_set.rigid_body_mut(body.0).unwrap();
+        let target = heading * speed.0;
+        let velocity = match acceleration {
+            Some(acceleration) => {
+                let current = player_body.velocity().linear;
+                let rate = if heading == Vec2::zero() {
+                    acceleration.decelerate
+                } else {
+                    acceleration.accelerate
+                };
+                approach(Vec2::new(current.x, current.y), target, rate * tick.as_secs_f32())
+            }
+            None => target,
+        };
+        player_body.set_linear_velocity(Vector2::new(velocity.x, velocity.y));
+        if let Some(direction) = Direction::facing(heading, animator.direction) {
+            animator.direction = direction;
         }
-        if input::is_key_down(ctx, Key::A) {
-            player_body.set_linear_velocity(Vector2::new(-PLAYER_SPEED, 0.0));
-            player.entity_animation.direction = Direction::Left;
-            player.animation_data.left.advance(delta_time);
+        if input.interact {
+            animator.perform(AnimState::Interact);
         }
-       
+    }
+}
+
+// Moves `from` up to `step` of the way towards `to` without overshooting.
+fn approach(from: Vec2<f32>, to: Vec2<f32>, step: f32) -> Vec2<f32> {
+    let left = to - from;
+    let distance = left.magnitude();
+    if distance <= step {
+        to
+    } else {
+        from + left * (step / distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagonals_are_no_faster() {
+        let input = InputState {
+            up: true,
+            right: true,
+            ..InputState::default()
+        };
+        let heading = input.heading();
+        assert!((heading.magnitude() - 1.0).abs() < 1e-6);
+        assert!(heading.x > 0.0 && heading.y < 0.0);
+        let cancelled = InputState {
+            left: true,
+            right: true,
+            ..InputState::default()
+        };
+        assert_eq!(cancelled.heading(), Vec2::zero());
+        let stick = InputState {
+            left: true,
+            stick: Vec2::new(0.0, 0.5),
+            ..InputState::default()
+        };
+        assert_eq!(stick.heading(), Vec2::new(0.0, 0.5));
+    }
+
+    #[test]
+    fn diagonals_keep_the_current_facing() {
+        let up_right = Vec2::new(1.0, -1.0);
+        assert_eq!(Direction::facing(up_right, Direction::Up), Some(Direction::Up));
+        assert_eq!(Direction::facing(up_right, Direction::Right), Some(Direction::Right));
+        assert_eq!(Direction::facing(up_right, Direction::Down), Some(Direction::Right));
+        // mostly up is up
+        let steep = Vec2::new(0.2, -1.0);
+        assert_eq!(Direction::facing(steep, Direction::Right), Some(Direction::Up));
+        assert_eq!(Direction::facing(Vec2::zero(), Direction::Left), None);
+    }
+
+    #[test]
+    fn approach_does_not_overshoot() {
+        let stopped = approach(Vec2::new(10.0, 0.0), Vec2::zero(), 4.0);
+        assert_eq!(stopped, Vec2::new(6.0, 0.0));
+        assert_eq!(approach(stopped, Vec2::zero(), 100.0), Vec2::zero());
     }
 }
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use hecs::{Entity, EntityBuilder, World};
+use nalgebra::base::Vector2;
+use nalgebra::geometry::Isometry2;
+use ncollide2d::shape::{Cuboid, ShapeHandle};
+use nphysics2d::material::{BasicMaterial, MaterialHandle};
+use nphysics2d::object::{
+    BodyPartHandle, BodyStatus, ColliderDesc, DefaultBodySet, DefaultColliderSet, RigidBodyDesc,
+};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tetra::graphics::Camera;
+use tetra::math::Vec2;
+use tetra::TetraError;
+use tiled::PropertyValue::StringValue;
+
+use crate::components::{
+    Acceleration, Ai, Animator, BodyHandle, CameraFollow, Character, CharacterSkin, Collided,
+    Direction, NPCState, Player, Position, PrefabName, RenderLayer, SheetSet, Speed, NPC,
+};
+use crate::map::{map_error, LoadedMap};
+use crate::sheet::Sheets;
+use crate::SCALE;
+
+// Files in here override the built-in prefabs of the same name, and can add
+// new ones. A prefab's name is its file name without the `.ron`.
+pub const PREFAB_DIR: &str = "resources/prefabs";
+
+const EMBEDDED_PREFABS: &[(&str, &str)] = &[
+    ("player", include_str!("../resources/prefabs/player.ron")),
+    ("npc", include_str!("../resources/prefabs/npc.ron")),
+];
+
+pub const PLAYER_PREFAB: &str = "player";
+pub const NPC_PREFAB: &str = "npc";
+
+// Components a prefab can ask for besides the body, position and skin every
+// character gets.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PrefabComponent {
+    Player,
+    Npc,
+    // follows the entity; only one should have it
+    Camera,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaterialDef {
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColliderDef {
+    pub half_extents: (f32, f32),
+    // nphysics' default material if left out
+    #[serde(default)]
+    pub material: Option<MaterialDef>,
+}
+
+// Everything needed to spawn one kind of character.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Prefab {
+    #[serde(skip)]
+    pub name: String,
+    pub components: Vec<PrefabComponent>,
+    pub collider: ColliderDef,
+    pub mass: f32,
+    pub speed: f32,
+    // only steers player characters for now
+    #[serde(default)]
+    pub acceleration: Option<Acceleration>,
+    pub sheets: SheetSet,
+    // index into `sheets`; a random one for each spawn if left out
+    #[serde(default)]
+    pub sheet: Option<usize>,
+    #[serde(default)]
+    pub ai: Option<Ai>,
+    // how the camera follows, with a Camera component; dead centre if left out
+    #[serde(default)]
+    pub camera: Option<CameraFollow>,
+}
+
+impl Prefab {
+    // Highest sheet index of this prefab's sheet set, out of (npc, player).
+    pub fn last_sheet(&self, sheets: &Sheets) -> usize {
+        let (npc, player) = sheets.lens();
+        match self.sheets {
+            SheetSet::Npc => npc,
+            SheetSet::Player => player,
+        }
+    }
+
+    pub fn character(&self, sheets: &Sheets, rng: &mut StdRng) -> Character {
+        let last = self.last_sheet(sheets);
+        match self.sheet {
+            Some(sheet) => Character(sheet, last),
+            None => Character(rng.gen_range(0, last + 1), last),
+        }
+    }
+}
+
+pub struct Prefabs(HashMap<String, Prefab>);
+
+impl Prefabs {
+    // The built-in prefabs, then whatever is in `PREFAB_DIR`.
+    pub fn load() -> tetra::Result<Prefabs> {
+        let mut prefabs = HashMap::new();
+        for (name, text) in EMBEDDED_PREFABS {
+            let path = Path::new(PREFAB_DIR).join(format!("{}.ron", name));
+            prefabs.insert(name.to_string(), parse(name, text, &path)?);
+        }
+        let dir = Path::new(PREFAB_DIR);
+        if dir.is_dir() {
+            let to_error = |reason| TetraError::FailedToLoadAsset {
+                reason,
+                path: dir.to_path_buf(),
+            };
+            for entry in fs::read_dir(dir).map_err(to_error)? {
+                let path = entry.map_err(to_error)?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("ron") {
+                    continue;
+                }
+                let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                let text =
+                    fs::read_to_string(&path).map_err(|reason| TetraError::FailedToLoadAsset {
+                        reason,
+                        path: path.clone(),
+                    })?;
+                prefabs.insert(name.clone(), parse(&name, &text, &path)?);
+            }
+        }
+        Ok(Prefabs(prefabs))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.0.get(name)
+    }
+
+    // A prefab that picks its sheet has to pick one that exists; the
+    // character would be drawn with no texture otherwise.
+    pub fn check_sheets(&self, sheets: &Sheets) -> tetra::Result {
+        for prefab in self.0.values() {
+            if let Some(sheet) = prefab.sheet {
+                let last = prefab.last_sheet(sheets);
+                if sheet > last {
+                    let path = Path::new(PREFAB_DIR).join(format!("{}.ron", prefab.name));
+                    let msg = format!(
+                        "sheet {} is past the last {:?} sheet, {}",
+                        sheet, prefab.sheets, last
+                    );
+                    return Err(map_error(&path, msg));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse(name: &str, text: &str, path: &Path) -> tetra::Result<Prefab> {
+    let mut prefab: Prefab =
+        ron::de::from_str(text).map_err(|err| map_error(path, err.to_string()))?;
+    prefab.name = name.to_string();
+    Ok(prefab)
+}
+
+// The prefab a spawn object asks for, or `default` if it doesn't say.
+pub fn object_prefab<'a>(object: &'a tiled::Object, default: &'a str) -> &'a str {
+    match object.properties.get("prefab") {
+        Some(StringValue(name)) => name.as_str(),
+        _ => default,
+    }
+}
+
+// Every prefab a map names has to exist.
+pub fn check_prefabs(map: &LoadedMap, prefabs: &Prefabs) -> tetra::Result {
+    for group in &map.map.object_groups {
+        for object in &group.objects {
+            if let Some(StringValue(name)) = object.properties.get("prefab") {
+                if prefabs.get(name).is_none() {
+                    return Err(map.error(format!(
+                        "object {} uses unknown prefab {}",
+                        object.name, name
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Where prefabs are spawned into, and what they need from the level to do it.
+pub struct Spawner<'a> {
+    pub colliders: &'a mut DefaultColliderSet<f32>,
+    pub bodies: &'a mut DefaultBodySet<f32>,
+    pub world: &'a mut World,
+    pub sheets: &'a Sheets,
+    // the window's size, for a prefab with a camera
+    pub viewport: Vec2<f32>,
+}
+
+pub fn spawn_prefab(
+    prefab: &Prefab,
+    position: Vector2<f32>,
+    character: Character,
+    direction: Direction,
+    state: NPCState,
+    spawner: &mut Spawner,
+) -> Entity {
+    let (half_width, half_height) = prefab.collider.half_extents;
+    let shape = ShapeHandle::new(Cuboid::new(Vector2::new(half_width, half_height)));
+    let body = RigidBodyDesc::new()
+        .position(Isometry2::new(position, nalgebra::zero()))
+        .gravity_enabled(false)
+        .status(BodyStatus::Dynamic)
+        .mass(prefab.mass)
+        .build();
+    let handle = spawner.bodies.insert(body);
+
+    let mut collider = ColliderDesc::new(shape);
+    if let Some(material) = prefab.collider.material {
+        collider = collider.material(MaterialHandle::new(BasicMaterial::new(
+            material.restitution,
+            material.friction,
+        )));
+    }
+    spawner.colliders.insert(collider.build(BodyPartHandle(handle, 0)));
+
+    let skin = CharacterSkin {
+        character,
+        sheets: prefab.sheets,
+    };
+    let mut animator = Animator::new(spawner.sheets.get(&skin).animations.clone());
+    animator.direction = direction;
+    let mut builder = EntityBuilder::new();
+    builder
+        .add(BodyHandle(handle))
+        .add(Position::new(Vec2::new(position.x, position.y)))
+        .add(RenderLayer(position.y))
+        .add(animator)
+        .add(skin)
+        .add(Speed(prefab.speed))
+        .add(PrefabName(prefab.name.clone()));
+    for component in &prefab.components {
+        match component {
+            PrefabComponent::Player => builder.add(Player),
+            PrefabComponent::Npc => builder.add(NPC),
+            PrefabComponent::Camera => {
+                // start on the entity rather than sweeping in from the origin
+                let mut camera = Camera::new(spawner.viewport.x, spawner.viewport.y);
+                camera.position = Vec2::new(position.x, position.y) * SCALE;
+                camera.update();
+                builder.add(camera).add(prefab.camera.unwrap_or_default())
+            }
+        };
+    }
+    if let Some(acceleration) = prefab.acceleration {
+        builder.add(acceleration);
+    }
+    if let Some(ai) = prefab.ai {
+        builder.add(ai).add(Collided(false)).add(state);
+    }
+    spawner.world.spawn(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn built_in_prefabs_parse() {
+        for (name, text) in EMBEDDED_PREFABS {
+            let prefab = parse(name, text, Path::new(name)).unwrap();
+            assert_eq!(prefab.name, *name);
+        }
+    }
+
+    #[test]
+    fn optional_fields_can_be_left_out() {
+        let text = "(
+            components: [Npc],
+            collider: (half_extents: (4.0, 4.0)),
+            mass: 1.0,
+            speed: 50.0,
+            sheets: Npc,
+        )";
+        let prefab = parse("blob", text, Path::new("blob.ron")).unwrap();
+        assert_eq!(prefab.collider.material, None);
+        assert_eq!(prefab.acceleration, None);
+        assert_eq!(prefab.sheet, None);
+        assert_eq!(prefab.ai, None);
+        assert_eq!(prefab.camera, None);
+    }
+
+    #[test]
+    fn sheets_past_the_end_are_rejected() {
+        let sheets = Sheets::load(crate::PLAYER_SHEETS, crate::NPC_SHEETS).unwrap();
+        let mut prefabs = Prefabs::load().unwrap();
+        assert!(prefabs.check_sheets(&sheets).is_ok());
+        let mut player = prefabs.get(PLAYER_PREFAB).unwrap().clone();
+        player.sheet = Some(crate::PLAYER_SHEETS.len());
+        prefabs.0.insert(player.name.clone(), player);
+        assert!(prefabs.check_sheets(&sheets).is_err());
+    }
+
+    #[test]
+    fn random_sheets_include_the_last() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut player = Prefabs::load().unwrap().get(PLAYER_PREFAB).unwrap().clone();
+        player.sheet = None;
+        let sheets = Sheets::load(crate::PLAYER_SHEETS, crate::NPC_SHEETS).unwrap();
+        let last = crate::PLAYER_SHEETS.len() - 1;
+        assert!((0..100).any(|_| player.character(&sheets, &mut rng).0 == last));
+        // a set of one always picks it
+        let sheets = Sheets::load(&crate::PLAYER_SHEETS[..1], crate::NPC_SHEETS).unwrap();
+        assert_eq!(player.character(&sheets, &mut rng).0, 0);
+    }
+}
@@ -19,12 +19,16 @@ pub use nphysics2d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld};
 pub use rand::distributions::{Distribution, Uniform};
 pub use rand::Rng;
 pub use std::collections::HashMap;
+pub use std::env;
 pub use std::fs;
+pub use std::path::{Path, PathBuf};
 pub use std::time::Duration;
 pub use tetra::graphics::animation::Animation;
 pub use tetra::graphics::{self, Camera, Color, DrawParams, Rectangle, Texture};
 pub use tetra::input::{self, Key};
 pub use tetra::math::Vec2;
+pub use tetra::time::Timestep;
+pub use tetra::window;
 pub use tetra::{Context, ContextBuilder, Event, State};
 pub use tiled::parse;
 pub use tiled::Layer;
@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use tetra::graphics::text::Text;
+use tetra::graphics::{self, DrawParams};
+use tetra::math::Vec2;
+use tetra::Context;
+
+use crate::components::{Animator, CharacterSkin, GameState, Position, RenderLayer, SheetSet};
+use crate::SCALE;
+
+// How many map layers, from the bottom, are drawn under the characters. The
+// layers above are drawn over them.
+pub const BACKGROUND_LAYERS: usize = 2;
+
+// Draws every character, furthest up the screen first so the ones in front
+// overlap the ones behind.
+pub fn draw_characters(ctx: &mut Context, state: &GameState, blend: f32) {
+    let mut query = state
+        .sim
+        .world
+        .query::<(&RenderLayer, &Position, &Animator, &CharacterSkin)>();
+    let mut characters: Vec<_> = query.iter().map(|(_, components)| components).collect();
+    characters.sort_by(|a, b| (a.0).0.partial_cmp(&(b.0).0).unwrap());
+    for (_layer, position, animator, skin) in characters {
+        let textures = match skin.sheets {
+            SheetSet::Player => &state.characters,
+            SheetSet::Npc => &state.npcs,
+        };
+        let texture = match textures.get(&skin.character.0) {
+            Some(texture) => texture,
+            None => continue,
+        };
+        let frame = match animator.current_frame() {
+            Some(frame) => frame,
+            None => continue,
+        };
+        graphics::draw(
+            ctx,
+            texture,
+            DrawParams::new()
+                .position(position.interpolated(blend) * SCALE)
+                .origin(state.sim.sheets.get(skin).origin)
+                .scale(Vec2::new(SCALE, SCALE))
+                .clip(frame),
+        );
+    }
+}
+
+// Draws the rebinding prompt and the latest message, if it's still up, in
+// the top left corner of the window.
+pub fn draw_overlay(ctx: &mut Context, state: &GameState) {
+    let mut lines = Vec::new();
+    if let Some(rebinding) = &state.rebinding {
+        lines.push(rebinding.prompt(&state.bindings));
+    }
+    if let Some((message, until)) = &state.message {
+        if Instant::now() < *until {
+            lines.push(message.clone());
+        }
+    }
+    if lines.is_empty() {
+        return;
+    }
+    // screen space, not the camera's
+    graphics::reset_transform_matrix(ctx);
+    let text = Text::new(lines.join("\n"), state.font.clone());
+    graphics::draw(ctx, &text, DrawParams::new().position(Vec2::new(8.0, 8.0)));
+}
@@ -0,0 +1,238 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use tetra::math::Vec2;
+use tetra::TetraError;
+
+use crate::map::map_error;
+use crate::player::InputState;
+
+// A replay is a seed, the map it started on and one line of input per tick:
+//
+//     seed 1234
+//     map resources/map/map5.tmx
+//     W......
+//     W..D...
+//     ....]..
+//
+// Each input line is `WASD[]E`, with a `.` for every action that is off.
+// The letters are the default keys, but a line records actions, so replays
+// don't depend on bindings. Lines from before Interact existed lack the `E`.
+// A pushed gamepad stick follows as `x,y`, e.g. `....... 0.5,-0.25`.
+const KEYS: [char; 7] = ['W', 'A', 'S', 'D', '[', ']', 'E'];
+
+pub struct Replay {
+    pub seed: u64,
+    pub map: Option<PathBuf>,
+    pub inputs: Vec<InputState>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> tetra::Result<Replay> {
+        let text = fs::read_to_string(path).map_err(|reason| TetraError::FailedToLoadAsset {
+            reason,
+            path: path.to_path_buf(),
+        })?;
+        Replay::parse(&text).map_err(|msg| map_error(path, msg))
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, String> {
+        let mut seed = None;
+        let mut map = None;
+        let mut inputs = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if let Some(value) = line.strip_prefix("seed ") {
+                let value = value.trim();
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("line {}: bad seed {}", number + 1, value))?,
+                );
+            } else if let Some(path) = line.strip_prefix("map ") {
+                map = Some(PathBuf::from(path.trim()));
+            } else if !line.trim().is_empty() {
+                inputs.push(decode(line).map_err(|msg| format!("line {}: {}", number + 1, msg))?);
+            }
+        }
+        Ok(Replay {
+            seed: seed.ok_or_else(|| "replay has no seed".to_string())?,
+            map,
+            inputs,
+        })
+    }
+
+    // The whole replay at once; the game writes its replays a tick at a time
+    // through `Recorder`.
+    #[cfg(test)]
+    pub fn to_text(&self) -> String {
+        let mut text = header(self.seed, self.map.as_deref());
+        for input in &self.inputs {
+            text.push_str(&encode(input));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// Appends every tick's input to a replay file as the game runs, so a crash
+// still leaves everything up to it on disk.
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, seed: u64, map: Option<&Path>) -> tetra::Result<Recorder> {
+        let to_error = |reason| TetraError::FailedToLoadAsset {
+            reason,
+            path: path.to_path_buf(),
+        };
+        let mut file = BufWriter::new(File::create(path).map_err(to_error)?);
+        file.write_all(header(seed, map).as_bytes())
+            .map_err(to_error)?;
+        Ok(Recorder { file })
+    }
+
+    pub fn record(&mut self, input: &InputState) -> io::Result<()> {
+        writeln!(self.file, "{}", encode(input))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn header(seed: u64, map: Option<&Path>) -> String {
+    let mut text = format!("seed {}\n", seed);
+    if let Some(map) = map {
+        text.push_str(&format!("map {}\n", map.display()));
+    }
+    text
+}
+
+fn keys(input: &InputState) -> [bool; 7] {
+    [
+        input.up,
+        input.left,
+        input.down,
+        input.right,
+        input.previous_character,
+        input.next_character,
+        input.interact,
+    ]
+}
+
+pub fn encode(input: &InputState) -> String {
+    let mut line: String = KEYS
+        .iter()
+        .zip(keys(input).iter())
+        .map(|(&key, &down)| if down { key } else { '.' })
+        .collect();
+    if input.stick != Vec2::zero() {
+        line.push_str(&format!(" {},{}", input.stick.x, input.stick.y));
+    }
+    line
+}
+
+pub fn decode(line: &str) -> Result<InputState, String> {
+    let mut fields = line.split_whitespace();
+    let chars: Vec<char> = fields.next().unwrap_or("").chars().collect();
+    if chars.len() != KEYS.len() && chars.len() != KEYS.len() - 1 {
+        return Err(format!("expected {} keys, got {:?}", KEYS.len(), line));
+    }
+    let mut down = [false; 7];
+    for (i, (&c, &key)) in chars.iter().zip(KEYS.iter()).enumerate() {
+        down[i] = match c {
+            '.' => false,
+            c if c == key => true,
+            c => return Err(format!("unexpected {:?} where {:?} goes", c, key)),
+        };
+    }
+    let stick = match fields.next() {
+        Some(stick) => decode_stick(stick).ok_or_else(|| format!("bad stick {:?}", stick))?,
+        None => Vec2::zero(),
+    };
+    if let Some(extra) = fields.next() {
+        return Err(format!("unexpected {:?} at the end", extra));
+    }
+    Ok(InputState {
+        up: down[0],
+        left: down[1],
+        down: down[2],
+        right: down[3],
+        previous_character: down[4],
+        next_character: down[5],
+        interact: down[6],
+        stick,
+    })
+}
+
+fn decode_stick(text: &str) -> Option<Vec2<f32>> {
+    let mut parts = text.splitn(2, ',');
+    let x = parts.next()?.parse().ok()?;
+    let y = parts.next()?.parse().ok()?;
+    Some(Vec2::new(x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_round_trips() {
+        let input = InputState {
+            up: true,
+            right: true,
+            next_character: true,
+            ..InputState::default()
+        };
+        assert_eq!(encode(&input), "W..D.].");
+        assert_eq!(decode("W..D.]."), Ok(input));
+        // from before Interact
+        assert_eq!(decode("W..D.]"), Ok(input));
+        assert!(decode("W..D").is_err());
+        assert!(decode("X......").is_err());
+        let stick = InputState {
+            stick: Vec2::new(0.3, -0.71),
+            ..InputState::default()
+        };
+        assert_eq!(encode(&stick), "....... 0.3,-0.71");
+        assert_eq!(decode(&encode(&stick)), Ok(stick));
+        assert!(decode("....... 0.3").is_err());
+    }
+
+    #[test]
+    fn replay_round_trips() {
+        let replay = Replay {
+            seed: 42,
+            map: Some(PathBuf::from("resources/map/map5.tmx")),
+            inputs: vec![InputState::default(), decode("..S...").unwrap()],
+        };
+        let parsed = Replay::parse(&replay.to_text()).unwrap();
+        assert_eq!(parsed.seed, 42);
+        assert_eq!(parsed.map, replay.map);
+        assert_eq!(parsed.inputs, replay.inputs);
+    }
+
+    #[test]
+    fn replay_reproduces_the_run() {
+        let inputs: Vec<_> = ["...D..", "..S...", ".A....", "W....]"]
+            .iter()
+            .flat_map(|line| vec![decode(line).unwrap(); 90])
+            .collect();
+        let run = |inputs: &[InputState]| {
+            let mut sim = crate::simulation::seeded(Some(9));
+            for input in inputs {
+                sim.step(input);
+            }
+            sim.player_position()
+        };
+        let replay = Replay {
+            seed: 9,
+            map: None,
+            inputs: inputs.clone(),
+        };
+        let replayed = Replay::parse(&replay.to_text()).unwrap();
+        assert_eq!(run(&inputs), run(&replayed.inputs));
+    }
+}
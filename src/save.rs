@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nalgebra::base::Vector2;
+use nalgebra::geometry::Isometry2;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use tetra::math::Vec2;
+use tetra::{Context, TetraError};
+
+use crate::components::{
+    Animator, BodyHandle, Character, CharacterSkin, Direction, GameState, NPCState, Player,
+    Position, PrefabName, RenderLayer, NPC,
+};
+use crate::contact::collider_entities;
+use crate::map::{map_error, LoadedMap};
+use crate::prefab::{spawn_prefab, Spawner, NPC_PREFAB};
+use crate::simulation::Simulation;
+
+pub const QUICKSAVE_PATH: &str = "quicksave.ron";
+
+// Just enough to put a level back the way it was: the map, and where every
+// character is and what they look like. Tiles and colliders come from the map.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SaveData {
+    pub map: PathBuf,
+    pub player: Option<CharacterSave>,
+    pub npcs: Vec<NpcSave>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CharacterSave {
+    pub position: (f32, f32),
+    pub character: Character,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NpcSave {
+    pub character: CharacterSave,
+    pub state: NPCState,
+    // saves from before prefabs only had the one kind of NPC
+    #[serde(default = "default_npc_prefab")]
+    pub prefab: String,
+}
+
+fn default_npc_prefab() -> String {
+    NPC_PREFAB.to_string()
+}
+
+impl SaveData {
+    pub fn read(path: &Path) -> tetra::Result<SaveData> {
+        let text = fs::read_to_string(path).map_err(|reason| TetraError::FailedToLoadAsset {
+            reason,
+            path: path.to_path_buf(),
+        })?;
+        ron::de::from_str(&text).map_err(|err| map_error(path, err.to_string()))
+    }
+
+    pub fn write(&self, path: &Path) -> tetra::Result {
+        let text = ron::ser::to_string_pretty(self, PrettyConfig::default())
+            .map_err(|err| map_error(path, err.to_string()))?;
+        fs::write(path, text).map_err(|reason| TetraError::FailedToLoadAsset {
+            reason,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Simulation {
+    pub fn save(&self) -> SaveData {
+        let character_save = |position: &Position, skin: &CharacterSkin, animator: &Animator| {
+            CharacterSave {
+                position: (position.current.x, position.current.y),
+                character: skin.character,
+                direction: animator.direction,
+            }
+        };
+        let player = self
+            .world
+            .query::<(&Player, &Position, &CharacterSkin, &Animator)>()
+            .iter()
+            .next()
+            .map(|(_, (_, position, skin, animator))| character_save(position, skin, animator));
+        let npcs = self
+            .world
+            .query::<(
+                &NPC,
+                &Position,
+                &CharacterSkin,
+                &Animator,
+                Option<&NPCState>,
+                &PrefabName,
+            )>()
+            .iter()
+            .map(|(_, (_, position, skin, animator, state, prefab))| NpcSave {
+                character: character_save(position, skin, animator),
+                state: state.copied().unwrap_or(NPCState::Idle),
+                prefab: prefab.0.clone(),
+            })
+            .collect();
+        SaveData {
+            map: self.map_path.clone(),
+            player,
+            npcs,
+        }
+    }
+
+    // Rebuilds the level `save` was taken on, then puts the characters back.
+    pub fn restore(&mut self, loaded: LoadedMap, save: &SaveData) -> tetra::Result {
+        if let Some(npc) = save.npcs.iter().find(|npc| self.prefabs.get(&npc.prefab).is_none()) {
+            return Err(map_error(
+                &save.map,
+                format!("saved NPC uses unknown prefab {}", npc.prefab),
+            ));
+        }
+        self.build_level(loaded, None, false)?;
+        if let Some(player) = &save.player {
+            let query = &mut self.world.query::<(
+                &Player,
+                &BodyHandle,
+                &mut Position,
+                &mut RenderLayer,
+                &mut CharacterSkin,
+                &mut Animator,
+            )>();
+            for (_id, (_player, handle, position, layer, skin, animator)) in query {
+                let at = Vector2::new(player.position.0, player.position.1);
+                if let Some(body) = self.body_set.rigid_body_mut(handle.0) {
+                    body.set_position(Isometry2::new(at, nalgebra::zero()));
+                }
+                *position = Position::new(Vec2::new(at.x, at.y));
+                layer.0 = at.y;
+                skin.character = player.character;
+                animator.animations = self.sheets.get(skin).animations.clone();
+                animator.direction = player.direction;
+            }
+        }
+        let mut spawner = Spawner {
+            colliders: &mut self.collider_set,
+            bodies: &mut self.body_set,
+            world: &mut self.world,
+            sheets: &self.sheets,
+            viewport: self.viewport,
+        };
+        for npc in &save.npcs {
+            spawn_prefab(
+                self.prefabs.get(&npc.prefab).unwrap(),
+                Vector2::new(npc.character.position.0, npc.character.position.1),
+                npc.character.character,
+                npc.character.direction,
+                npc.state,
+                &mut spawner,
+            );
+        }
+        self.collider_entities = collider_entities(&self.world, &self.collider_set);
+        Ok(())
+    }
+}
+
+impl GameState {
+    pub fn quicksave(&self) -> tetra::Result {
+        self.sim.save().write(Path::new(QUICKSAVE_PATH))
+    }
+
+    pub fn quickload(&mut self, ctx: &mut Context) -> tetra::Result {
+        // A replay only holds input, so it can't reproduce a jump to a save,
+        // and one being played back would carry on against the wrong state.
+        if self.recorder.is_some() {
+            return Err(TetraError::PlatformError(
+                "can't quickload while recording a replay".into(),
+            ));
+        }
+        if self.replay.is_some() {
+            return Err(TetraError::PlatformError(
+                "can't quickload while playing a replay".into(),
+            ));
+        }
+        let save = SaveData::read(Path::new(QUICKSAVE_PATH))?;
+        let loaded = LoadedMap::reload(&save.map)?;
+        let texture_map = loaded.load_textures(ctx)?;
+        self.sim.restore(loaded, &save)?;
+        self.texture_map = texture_map;
+        self.accumulator = Default::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::InputState;
+    use crate::simulation::seeded;
+
+    fn simulation() -> Simulation {
+        seeded(Some(3))
+    }
+
+    #[test]
+    fn save_survives_ron() {
+        let save = simulation().save();
+        let text = ron::ser::to_string_pretty(&save, PrettyConfig::default()).unwrap();
+        let parsed: SaveData = ron::de::from_str(&text).unwrap();
+        assert_eq!(parsed, save);
+    }
+
+    #[test]
+    fn restore_puts_everyone_back() {
+        let mut sim = simulation();
+        let idle = InputState::default();
+        for _ in 0..300 {
+            sim.step(&idle);
+        }
+        let save = sim.save();
+        assert!(save.player.is_some());
+        assert!(!save.npcs.is_empty());
+        for _ in 0..300 {
+            sim.step(&idle);
+        }
+        assert_ne!(sim.save(), save);
+        let loaded = LoadedMap::reload(&save.map).unwrap();
+        sim.restore(loaded, &save).unwrap();
+        assert_eq!(sim.save(), save);
+    }
+}
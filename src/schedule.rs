@@ -0,0 +1,249 @@
+use std::time::{Duration, Instant};
+
+use crate::player::InputState;
+
+// Stages run in this order. The first four make up a tick and run from
+// `Simulation::step`; camera and render prep run once per frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Input,
+    Ai,
+    Physics,
+    PostPhysics,
+    Camera,
+    RenderPrep,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Input,
+        Stage::Ai,
+        Stage::Physics,
+        Stage::PostPhysics,
+        Stage::Camera,
+        Stage::RenderPrep,
+    ];
+    pub const TICK: [Stage; 4] = [Stage::Input, Stage::Ai, Stage::Physics, Stage::PostPhysics];
+    pub const FRAME: [Stage; 2] = [Stage::Camera, Stage::RenderPrep];
+}
+
+// What a system gets to look at besides the state it updates. `blend` is how
+// far the frame is between ticks, and `delta` how long since the last frame;
+// both are always 0 during a tick.
+pub struct Frame<'a> {
+    pub input: &'a InputState,
+    pub blend: f32,
+    pub delta: Duration,
+}
+
+pub type System<T> = fn(&mut T, &Frame);
+
+pub struct SystemEntry<T> {
+    pub name: &'static str,
+    pub stage: Stage,
+    pub enabled: bool,
+    // how long the system took the last time it ran
+    pub last_run: Duration,
+    after: Vec<&'static str>,
+    run: System<T>,
+}
+
+impl<T> SystemEntry<T> {
+    // Runs this system after `name`, which has to be in the same stage or
+    // an earlier one.
+    pub fn after(&mut self, name: &'static str) -> &mut SystemEntry<T> {
+        self.after.push(name);
+        self
+    }
+}
+
+// Named systems grouped into stages. Within a stage systems run in the order
+// they were added, unless an `after` says otherwise.
+pub struct Schedule<T> {
+    systems: Vec<SystemEntry<T>>,
+    // indices into `systems`, worked out again whenever one is added
+    order: Option<Vec<usize>>,
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Schedule<T> {
+        Schedule {
+            systems: Vec::new(),
+            order: None,
+        }
+    }
+}
+
+impl<T> Schedule<T> {
+    pub fn add(
+        &mut self,
+        stage: Stage,
+        name: &'static str,
+        run: System<T>,
+    ) -> &mut SystemEntry<T> {
+        assert!(self.get(name).is_none(), "system {} added twice", name);
+        self.order = None;
+        self.systems.push(SystemEntry {
+            name,
+            stage,
+            enabled: true,
+            last_run: Duration::from_secs(0),
+            after: Vec::new(),
+            run,
+        });
+        self.systems.last_mut().unwrap()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SystemEntry<T>> {
+        self.systems.iter().find(|system| system.name == name)
+    }
+
+    // Turns a system on or off. Returns false if there's no such system.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.systems.iter_mut().find(|system| system.name == name) {
+            Some(system) => {
+                system.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Every system in the order it runs.
+    pub fn systems(&mut self) -> impl Iterator<Item = &SystemEntry<T>> {
+        let order = self.order();
+        let systems = &self.systems;
+        order.into_iter().map(move |index| &systems[index])
+    }
+
+    pub fn run(&mut self, stages: &[Stage], state: &mut T, frame: &Frame) {
+        for index in self.order() {
+            let system = &mut self.systems[index];
+            if !system.enabled || !stages.contains(&system.stage) {
+                continue;
+            }
+            let start = Instant::now();
+            (system.run)(state, frame);
+            system.last_run = start.elapsed();
+        }
+    }
+
+    fn order(&mut self) -> Vec<usize> {
+        if self.order.is_none() {
+            self.order = Some(self.sort());
+        }
+        self.order.clone().unwrap()
+    }
+
+    fn sort(&self) -> Vec<usize> {
+        for system in &self.systems {
+            for name in &system.after {
+                assert!(
+                    self.get(name).is_some(),
+                    "{} runs after {}, which doesn't exist",
+                    system.name,
+                    name
+                );
+            }
+        }
+        let mut placed = vec![false; self.systems.len()];
+        let mut order = Vec::new();
+        for &stage in &Stage::ALL {
+            // take the first system whose dependencies have all run, until
+            // the stage is empty
+            loop {
+                let ready = (0..self.systems.len()).find(|&index| {
+                    let system = &self.systems[index];
+                    !placed[index]
+                        && system.stage == stage
+                        && system.after.iter().all(|name| {
+                            self.systems
+                                .iter()
+                                .zip(&placed)
+                                .any(|(other, &done)| done && other.name == *name)
+                        })
+                });
+                match ready {
+                    Some(index) => {
+                        placed[index] = true;
+                        order.push(index);
+                    }
+                    None => break,
+                }
+            }
+            let stuck: Vec<_> = (0..self.systems.len())
+                .filter(|&index| !placed[index] && self.systems[index].stage == stage)
+                .map(|index| self.systems[index].name)
+                .collect();
+            assert!(
+                stuck.is_empty(),
+                "{:?} systems {:?} wait on each other or on a later stage",
+                stage,
+                stuck
+            );
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_all(schedule: &mut Schedule<Vec<&'static str>>) -> Vec<&'static str> {
+        let mut ran = Vec::new();
+        let input = InputState::default();
+        let frame = Frame {
+            input: &input,
+            blend: 0.0,
+            delta: Duration::from_secs(0),
+        };
+        schedule.run(&Stage::ALL, &mut ran, &frame);
+        ran
+    }
+
+    #[test]
+    fn stages_then_constraints() {
+        let mut schedule: Schedule<Vec<&str>> = Schedule::default();
+        schedule.add(Stage::Camera, "camera", |ran, _| ran.push("camera"));
+        schedule.add(Stage::Ai, "late", |ran, _| ran.push("late")).after("early");
+        schedule.add(Stage::Ai, "early", |ran, _| ran.push("early"));
+        schedule.add(Stage::Input, "input", |ran, _| ran.push("input"));
+        assert_eq!(run_all(&mut schedule), ["input", "early", "late", "camera"]);
+    }
+
+    #[test]
+    fn disabled_systems_are_skipped() {
+        let mut schedule: Schedule<Vec<&str>> = Schedule::default();
+        schedule.add(Stage::Input, "a", |ran, _| ran.push("a"));
+        schedule.add(Stage::Input, "b", |ran, _| ran.push("b"));
+        assert!(schedule.set_enabled("a", false));
+        assert!(!schedule.set_enabled("missing", false));
+        assert_eq!(run_all(&mut schedule), ["b"]);
+    }
+
+    #[test]
+    fn only_the_given_stages_run() {
+        let mut schedule: Schedule<Vec<&str>> = Schedule::default();
+        schedule.add(Stage::Physics, "physics", |ran, _| ran.push("physics"));
+        schedule.add(Stage::RenderPrep, "prep", |ran, _| ran.push("prep"));
+        let mut ran = Vec::new();
+        let input = InputState::default();
+        let frame = Frame {
+            input: &input,
+            blend: 0.5,
+            delta: Duration::from_millis(16),
+        };
+        schedule.run(&Stage::FRAME, &mut ran, &frame);
+        assert_eq!(ran, ["prep"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn cycles_are_caught() {
+        let mut schedule: Schedule<Vec<&str>> = Schedule::default();
+        schedule.add(Stage::Ai, "a", |ran, _| ran.push("a")).after("b");
+        schedule.add(Stage::Ai, "b", |ran, _| ran.push("b")).after("a");
+        run_all(&mut schedule);
+    }
+}
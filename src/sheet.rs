@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tetra::graphics::Rectangle;
+use tetra::math::Vec2;
+
+use crate::aseprite;
+use crate::components::{Anim, AnimState, AnimationData, CharacterSkin, Direction, SheetSet};
+use crate::map::map_error;
+
+// A character sprite sheet baked into the binary, with the descriptor that
+// says how its frames are laid out.
+pub struct SheetSource {
+    // file name under `resources` without the extension; the image is
+    // `<name>.png`, the descriptor `<name>.ron` and an Aseprite export, if
+    // there is one, `<name>.json`
+    pub name: &'static str,
+    pub image: &'static [u8],
+    pub descriptor: &'static str,
+    pub aseprite: Option<&'static str>,
+}
+
+impl SheetSource {
+    fn path(&self, extension: &str) -> PathBuf {
+        Path::new("resources").join(format!("{}.{}", self.name, extension))
+    }
+}
+
+// One animation: `frames` frames side by side along row `row`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationDef {
+    pub row: u32,
+    #[serde(default)]
+    pub column: u32,
+    pub frames: usize,
+    // seconds each frame is shown for
+    pub duration: f64,
+}
+
+// How a character sheet is cut up. Frame sizes and the origin are in sheet
+// pixels; the origin is the point that sits on the character's position.
+// Animations are named `<state>_<direction>`, like Aseprite tags, and
+// override tags of the same name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SheetDescriptor {
+    pub frame_size: (f32, f32),
+    pub origin: (f32, f32),
+    #[serde(default)]
+    pub animations: BTreeMap<String, AnimationDef>,
+}
+
+impl SheetDescriptor {
+    pub fn parse(text: &str, path: &Path) -> tetra::Result<SheetDescriptor> {
+        ron::de::from_str(text).map_err(|err| map_error(path, err.to_string()))
+    }
+
+    fn anim(&self, def: &AnimationDef) -> Anim {
+        let (width, height) = self.frame_size;
+        let frames: Vec<_> = Rectangle::row(
+            def.column as f32 * width,
+            def.row as f32 * height,
+            width,
+            height,
+        )
+        .take(def.frames)
+        .collect();
+        Anim::new(&frames, Duration::from_secs_f64(def.duration))
+    }
+}
+
+// A sheet ready to animate with.
+pub struct Sheet {
+    pub origin: Vec2<f32>,
+    pub animations: AnimationData,
+}
+
+impl Sheet {
+    pub fn load(source: &SheetSource) -> tetra::Result<Sheet> {
+        let path = source.path("ron");
+        let descriptor = SheetDescriptor::parse(source.descriptor, &path)?;
+        let mut animations = AnimationData::default();
+        if let Some(json) = source.aseprite {
+            let tags = aseprite::parse(json).map_err(|msg| map_error(&source.path("json"), msg))?;
+            // tags that aren't animation names are for someone else
+            for (name, anim) in tags {
+                if let Some((state, direction)) = AnimationData::key(&name) {
+                    animations.insert(state, direction, anim);
+                }
+            }
+        }
+        for (name, def) in &descriptor.animations {
+            let (state, direction) = AnimationData::key(name)
+                .ok_or_else(|| map_error(&path, format!("unknown animation {}", name)))?;
+            if def.frames == 0 {
+                return Err(map_error(&path, format!("{} has no frames", name)));
+            }
+            animations.insert(state, direction, descriptor.anim(def));
+        }
+        // characters can always walk; everything else is optional
+        for &direction in &Direction::ALL {
+            if animations.get(AnimState::Walk, direction).is_none() {
+                return Err(map_error(
+                    &path,
+                    format!("no walk_{:?} animation", direction).to_lowercase(),
+                ));
+            }
+        }
+        Ok(Sheet {
+            origin: Vec2::new(descriptor.origin.0, descriptor.origin.1),
+            animations,
+        })
+    }
+}
+
+// Every player and NPC sheet, in sheet index order.
+pub struct Sheets {
+    pub player: Vec<Sheet>,
+    pub npc: Vec<Sheet>,
+}
+
+impl Sheets {
+    pub fn load(player: &[SheetSource], npc: &[SheetSource]) -> tetra::Result<Sheets> {
+        Ok(Sheets {
+            player: player.iter().map(Sheet::load).collect::<tetra::Result<_>>()?,
+            npc: npc.iter().map(Sheet::load).collect::<tetra::Result<_>>()?,
+        })
+    }
+
+    // Highest sheet index of each set, as (npc, player).
+    pub fn lens(&self) -> (usize, usize) {
+        (self.npc.len() - 1, self.player.len() - 1)
+    }
+
+    // The sheet for a skin. An index past the end, e.g. from a prefab naming
+    // a sheet that doesn't exist, gets the last sheet of the set.
+    pub fn get(&self, skin: &CharacterSkin) -> &Sheet {
+        let sheets = match skin.sheets {
+            SheetSet::Player => &self.player,
+            SheetSet::Npc => &self.npc,
+        };
+        &sheets[skin.character.0.min(sheets.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(descriptor: &'static str, aseprite: Option<&'static str>) -> SheetSource {
+        SheetSource {
+            name: "test",
+            image: &[],
+            descriptor,
+            aseprite,
+        }
+    }
+
+    #[test]
+    fn built_in_sheets_load() {
+        let sheets = Sheets::load(crate::PLAYER_SHEETS, crate::NPC_SHEETS).unwrap();
+        assert_eq!(sheets.player.len(), crate::PLAYER_SHEETS.len());
+        assert_eq!(sheets.npc.len(), crate::NPC_SHEETS.len());
+    }
+
+    #[test]
+    fn frames_follow_the_descriptor() {
+        let descriptor = r#"(
+            frame_size: (16.0, 24.0),
+            origin: (8.0, 20.0),
+            animations: {
+                "walk_down": (row: 0, frames: 4, duration: 0.1),
+                "walk_left": (row: 1, frames: 2, duration: 0.1),
+                "walk_right": (row: 1, column: 2, frames: 2, duration: 0.1),
+                "walk_up": (row: 2, frames: 1, duration: 0.5),
+                "idle_down": (row: 3, frames: 2, duration: 1.0),
+            },
+        )"#;
+        let sheet = Sheet::load(&source(descriptor, None)).unwrap();
+        let animations = &sheet.animations;
+        let walk = |direction| animations.get(AnimState::Walk, direction).unwrap();
+        assert_eq!(walk(Direction::Down).frames.len(), 4);
+        assert_eq!(
+            walk(Direction::Right).frames[0],
+            Rectangle::new(32.0, 24.0, 16.0, 24.0)
+        );
+        assert_eq!(walk(Direction::Up).durations[0], Duration::from_secs_f64(0.5));
+        assert!(animations.get(AnimState::Idle, Direction::Down).is_some());
+        assert!(animations.get(AnimState::Idle, Direction::Up).is_none());
+    }
+
+    #[test]
+    fn aseprite_tags_fill_in_animations() {
+        let descriptor = r#"(
+            frame_size: (8.0, 8.0),
+            origin: (4.0, 7.0),
+            animations: { "walk_up": (row: 3, frames: 1, duration: 0.2) },
+        )"#;
+        let json = r#"{
+            "frames": [
+                { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 100 },
+                { "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "duration": 300 }
+            ],
+            "meta": { "frameTags": [
+                { "name": "walk_down", "from": 0, "to": 1 },
+                { "name": "walk_left", "from": 0, "to": 1 },
+                { "name": "walk_right", "from": 0, "to": 1 },
+                { "name": "walk_up", "from": 0, "to": 1 },
+                { "name": "Layer 1", "from": 0, "to": 0 }
+            ] }
+        }"#;
+        let sheet = Sheet::load(&source(descriptor, Some(json))).unwrap();
+        let down = sheet.animations.get(AnimState::Walk, Direction::Down).unwrap();
+        assert_eq!(down.durations, [Duration::from_millis(100), Duration::from_millis(300)]);
+        // the descriptor wins over a tag of the same name
+        let up = sheet.animations.get(AnimState::Walk, Direction::Up).unwrap();
+        assert_eq!(up.frames, [Rectangle::new(0.0, 24.0, 8.0, 8.0)]);
+    }
+
+    #[test]
+    fn a_walk_cycle_is_required() {
+        let descriptor = r#"(
+            frame_size: (16.0, 24.0),
+            origin: (8.0, 20.0),
+            animations: { "walk_down": (row: 0, frames: 4, duration: 0.1) },
+        )"#;
+        assert!(Sheet::load(&source(descriptor, None)).is_err());
+    }
+
+    #[test]
+    fn empty_animations_are_rejected() {
+        let descriptor = r#"(
+            frame_size: (16.0, 24.0),
+            origin: (8.0, 20.0),
+            animations: {
+                "walk_down": (row: 0, frames: 4, duration: 0.1),
+                "walk_left": (row: 1, frames: 0, duration: 0.1),
+                "walk_right": (row: 2, frames: 4, duration: 0.1),
+                "walk_up": (row: 3, frames: 4, duration: 0.1),
+            },
+        )"#;
+        assert!(Sheet::load(&source(descriptor, None)).is_err());
+    }
+}
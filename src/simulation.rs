@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hecs::{Entity, World};
+use nalgebra::base::Vector2;
+use nphysics2d::force_generator::DefaultForceGeneratorSet;
+use nphysics2d::joint::DefaultJointConstraintSet;
+use nphysics2d::object::{DefaultBodySet, DefaultColliderHandle, DefaultColliderSet};
+use nphysics2d::world::{DefaultGeometricalWorld, DefaultMechanicalWorld};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tetra::math::Vec2;
+use tiled::Layer;
+
+use crate::animation::animate;
+use crate::camera;
+use crate::components::{
+    BodyHandle, Collided, Contact, ContactPhase, Player, Position, RenderLayer,
+    Sprite, TriggerEvent, NPC,
+};
+use crate::contact::contact_events;
+use crate::npc::npc_update;
+use crate::player::{player_update, switch_character, InputState};
+use crate::prefab::Prefabs;
+use crate::schedule::{Frame, Schedule, Stage};
+use crate::sheet::Sheets;
+use crate::trigger::trigger_events;
+
+// Everything that makes up a running level, minus anything that needs a
+// window: textures live on GameState. Can be stepped on its own, e.g. in tests.
+pub struct Simulation {
+    pub world: World,
+    pub sprite_map: HashMap<u32, Sprite>,
+    pub layers: Vec<Layer>,
+    pub tile_size: Vec2<f32>,
+    // the whole map in map pixels, which the camera keeps inside
+    pub map_size: Vec2<f32>,
+    pub tick: Duration,
+    pub mechanical_world: DefaultMechanicalWorld<f32>,
+    pub geometrical_world: DefaultGeometricalWorld<f32>,
+    pub body_set: DefaultBodySet<f32>,
+    pub collider_set: DefaultColliderSet<f32>,
+    pub constraint_set: DefaultJointConstraintSet<f32>,
+    pub force_gen_set: DefaultForceGeneratorSet<f32>,
+    pub sheets: Sheets,
+    pub prefabs: Prefabs,
+    // every random decision draws from this, so a run can be replayed
+    pub rng: StdRng,
+    // set from the command line; wins over a map's own seed
+    pub seed: Option<u64>,
+    // size of the camera given to a newly spawned player
+    pub viewport: Vec2<f32>,
+    pub map_path: PathBuf,
+    // false until the player has stepped off the warp they arrived on
+    pub warp_armed: bool,
+    // set when a tick took the player to another map, for whoever draws it
+    // to load its textures; never cleared here
+    pub warped: bool,
+    pub collider_entities: HashMap<DefaultColliderHandle, Entity>,
+    // contacts and trigger enters/exits from the latest tick
+    pub contacts: Vec<Contact>,
+    pub trigger_events: Vec<TriggerEvent>,
+    pub schedule: Schedule<Simulation>,
+}
+
+impl Simulation {
+    pub fn new(
+        sheets: Sheets,
+        prefabs: Prefabs,
+        viewport: Vec2<f32>,
+        tick: Duration,
+        seed: Option<u64>,
+    ) -> Simulation {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Simulation {
+            world: World::new(),
+            sprite_map: HashMap::new(),
+            layers: Vec::new(),
+            tile_size: Vec2::zero(),
+            map_size: Vec2::zero(),
+            tick,
+            mechanical_world: DefaultMechanicalWorld::new(Vector2::new(0.0, 0.0)),
+            geometrical_world: DefaultGeometricalWorld::new(),
+            body_set: DefaultBodySet::new(),
+            collider_set: DefaultColliderSet::new(),
+            constraint_set: DefaultJointConstraintSet::new(),
+            force_gen_set: DefaultForceGeneratorSet::new(),
+            sheets,
+            prefabs,
+            rng,
+            seed,
+            viewport,
+            map_path: PathBuf::new(),
+            warp_armed: false,
+            warped: false,
+            collider_entities: HashMap::new(),
+            contacts: Vec::new(),
+            trigger_events: Vec::new(),
+            schedule: default_schedule(),
+        }
+    }
+
+    // One fixed tick of gameplay and physics. Everything the player does
+    // comes in through `input`, which is what makes runs replayable.
+    pub fn step(&mut self, input: &InputState) {
+        self.contacts.clear();
+        self.trigger_events.clear();
+        let frame = Frame {
+            input,
+            blend: 0.0,
+            delta: Duration::from_secs(0),
+        };
+        self.run_stages(&Stage::TICK, &frame);
+    }
+
+    pub fn run_stages(&mut self, stages: &[Stage], frame: &Frame) {
+        // systems get the whole simulation, so the schedule is lent out
+        // while they run
+        let mut schedule = mem::take(&mut self.schedule);
+        schedule.run(stages, self, frame);
+        self.schedule = schedule;
+    }
+
+    fn step_physics(&mut self) {
+        self.mechanical_world.set_timestep(self.tick.as_secs_f32());
+        self.mechanical_world.step(
+            &mut self.geometrical_world,
+            &mut self.body_set,
+            &mut self.collider_set,
+            &mut self.constraint_set,
+            &mut self.force_gen_set,
+        );
+    }
+
+    pub fn player_position(&self) -> Option<Vec2<f32>> {
+        let mut query = self.world.query::<(&Player, &Position)>();
+        let (_id, (_player, position)) = query.iter().next()?;
+        Some(position.current)
+    }
+}
+
+// The systems every simulation runs, in the order they run.
+fn default_schedule() -> Schedule<Simulation> {
+    let mut schedule = Schedule::default();
+    schedule.add(Stage::Input, "switch_character", |sim, frame| {
+        switch_character(&mut sim.world, frame.input, &sim.sheets)
+    });
+    schedule.add(Stage::Input, "remember_positions", |sim, _| {
+        for (_id, position) in &mut sim.world.query::<&mut Position>() {
+            position.previous = position.current;
+        }
+    });
+    schedule.add(Stage::Input, "player_update", |sim, frame| {
+        player_update(&mut sim.body_set, &mut sim.world, frame.input, sim.tick)
+    });
+    schedule.add(Stage::Ai, "npc_update", |sim, _| {
+        npc_update(&mut sim.body_set, &mut sim.world, &mut sim.rng)
+    });
+    schedule.add(Stage::Physics, "physics", |sim, _| sim.step_physics());
+    schedule.add(Stage::PostPhysics, "sync_positions", |sim, _| {
+        sync_positions(&sim.body_set, &mut sim.world)
+    });
+    schedule
+        .add(Stage::PostPhysics, "animate", |sim, _| animate(&mut sim.world, sim.tick))
+        .after("sync_positions");
+    schedule.add(Stage::PostPhysics, "contacts", |sim, _| {
+        let contacts = contact_events(&sim.geometrical_world, &sim.collider_entities);
+        handle_contacts(&contacts, &mut sim.world);
+        sim.contacts.extend(contacts);
+    });
+    schedule.add(Stage::PostPhysics, "triggers", |sim, _| {
+        let events = trigger_events(&sim.geometrical_world, &sim.collider_entities, &sim.world);
+        sim.trigger_events.extend(events);
+    });
+    // swaps the whole level out, so nothing else in the tick comes after it
+    schedule
+        .add(Stage::PostPhysics, "warps", |sim, _| sim.follow_warp())
+        .after("triggers");
+    schedule.add(Stage::Camera, "camera_follow", |sim, frame| {
+        camera::follow(&mut sim.world, sim.map_size, sim.viewport, frame.blend, frame.delta)
+    });
+    // characters further down the screen are drawn in front
+    schedule.add(Stage::RenderPrep, "render_layers", |sim, frame| {
+        for (_id, (_body, position, layer)) in
+            &mut sim.world.query::<(&BodyHandle, &Position, &mut RenderLayer)>()
+        {
+            layer.0 = position.interpolated(frame.blend).y;
+        }
+    });
+    schedule
+}
+
+// Copies body positions into the ECS after a physics step.
+pub fn sync_positions(body_set: &DefaultBodySet<f32>, world: &mut World) {
+    for (_id, (handle, position)) in &mut world.query::<(&BodyHandle, &mut Position)>() {
+        if let Some(body) = body_set.rigid_body(handle.0) {
+            let translation = body.position().translation.vector;
+            position.current = Vec2::new(translation.x, translation.y);
+        }
+    }
+}
+
+// NPCs pick a new direction when they bump into anything.
+fn handle_contacts(contacts: &[Contact], world: &mut World) {
+    for contact in contacts {
+        if contact.phase != ContactPhase::Started {
+            continue;
+        }
+        for &entity in &[contact.a, contact.b] {
+            if let Ok(mut collided) = world.get_mut::<Collided>(entity) {
+                collided.0 = true;
+            }
+        }
+    }
+}
+
+// The built-in map at the window's size, for tests anywhere in the crate.
+#[cfg(test)]
+pub fn seeded(seed: Option<u64>) -> Simulation {
+    let viewport = Vec2::new(crate::WINDOW_WIDTH, crate::WINDOW_HEIGHT);
+    let mut sim = crate::new_simulation(viewport, seed).unwrap();
+    let loaded = crate::map::LoadedMap::load(None).unwrap();
+    sim.load_level(loaded, None).unwrap();
+    sim
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{
+        Anim, AnimState, AnimationData, Animator, CharacterSkin, Direction, SpawnBounds, Warp,
+    };
+    use crate::map::LoadedMap;
+    use tetra::graphics::Rectangle;
+
+    fn simulation() -> Simulation {
+        seeded(None)
+    }
+
+    fn npc_positions(sim: &Simulation) -> Vec<(f32, f32)> {
+        sim.world
+            .query::<(&NPC, &Position)>()
+            .iter()
+            .map(|(_, (_, position))| (position.current.x, position.current.y))
+            .collect()
+    }
+
+    #[test]
+    fn runs_without_a_window() {
+        let mut sim = simulation();
+        let idle = InputState::default();
+        for _ in 0..5000 {
+            sim.step(&idle);
+        }
+        assert!(sim.player_position().is_some());
+    }
+
+    #[test]
+    fn input_moves_the_player() {
+        let mut sim = simulation();
+        let start = sim.player_position().unwrap();
+        let inputs = [
+            InputState {
+                right: true,
+                ..InputState::default()
+            },
+            InputState {
+                down: true,
+                ..InputState::default()
+            },
+            InputState {
+                left: true,
+                ..InputState::default()
+            },
+            InputState {
+                up: true,
+                ..InputState::default()
+            },
+        ];
+        // at least one way out of the spawn point has to be open
+        let moved = inputs.iter().any(|input| {
+            for _ in 0..30 {
+                sim.step(input);
+            }
+            sim.player_position().unwrap() != start
+        });
+        assert!(moved);
+    }
+
+    #[test]
+    fn same_seed_same_npcs() {
+        let mut a = seeded(Some(7));
+        let mut b = seeded(Some(7));
+        assert_eq!(npc_positions(&a), npc_positions(&b));
+        let idle = InputState::default();
+        for _ in 0..2000 {
+            a.step(&idle);
+            b.step(&idle);
+        }
+        assert!(!npc_positions(&a).is_empty());
+        assert_eq!(npc_positions(&a), npc_positions(&b));
+    }
+
+    #[test]
+    fn warps_change_map_without_a_window() {
+        let mut sim = simulation();
+        let idle = InputState::default();
+        // arrive, then step off onto the warp
+        sim.step(&idle);
+        let at = sim.player_position().unwrap();
+        sim.world.spawn((Warp {
+            bounds: SpawnBounds {
+                x: (at.x - 16.0, at.x + 16.0),
+                y: (at.y - 16.0, at.y + 16.0),
+            },
+            map: "map5.tmx".to_string(),
+            spawn: None,
+        },));
+        sim.step(&idle);
+        assert!(sim.warped);
+        // a fresh copy of the map, without the warp added above
+        assert_eq!(sim.world.query::<&Warp>().iter().count(), 0);
+        assert!(sim.player_position().is_some());
+    }
+
+    fn set_player_sheet(sim: &mut Simulation, index: usize) {
+        for (_id, (_player, skin)) in &mut sim.world.query::<(&Player, &mut CharacterSkin)>() {
+            skin.character.0 = index;
+        }
+    }
+
+    fn player_walk_frame(sim: &Simulation) -> Rectangle {
+        let mut query = sim.world.query::<(&Player, &Animator)>();
+        let (_id, (_player, animator)) = query.iter().next().unwrap();
+        animator.animations.get(AnimState::Walk, Direction::Down).unwrap().frames[0]
+    }
+
+    #[test]
+    fn skins_bring_their_own_layout() {
+        let mut sim = simulation();
+        // a second player sheet laid out unlike the first
+        let frame = Rectangle::new(64.0, 32.0, 16.0, 16.0);
+        let mut animations = AnimationData::default();
+        for &direction in &Direction::ALL {
+            let anim = Anim::new(&[frame], Duration::from_millis(100));
+            animations.insert(AnimState::Walk, direction, anim);
+        }
+        sim.sheets.player[1].animations = animations;
+        set_player_sheet(&mut sim, 1);
+        let save = sim.save();
+        // carried over to a new map
+        sim.load_level(LoadedMap::load(None).unwrap(), None).unwrap();
+        assert_eq!(player_walk_frame(&sim), frame);
+        // and put back by a quickload
+        set_player_sheet(&mut sim, 0);
+        sim.restore(LoadedMap::reload(&save.map).unwrap(), &save).unwrap();
+        assert_eq!(player_walk_frame(&sim), frame);
+    }
+}
@@ -0,0 +1,234 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use tetra::graphics::Rectangle;
+use tetra::math::Vec2;
+
+// Tiled's flip flags. The diagonal flip (swap x and y) is applied first, then
+// the horizontal and vertical mirrors. Tiled's 90° rotations are combinations
+// of these, e.g. diagonal + horizontal is a clockwise quarter turn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileTransform {
+    pub flip_h: bool,
+    pub flip_v: bool,
+    pub flip_d: bool,
+}
+
+impl TileTransform {
+    pub fn from_tile(tile: &tiled::LayerTile) -> TileTransform {
+        TileTransform {
+            flip_h: tile.flip_h,
+            flip_v: tile.flip_v,
+            flip_d: tile.flip_d,
+        }
+    }
+
+    // Maps a point relative to the centre of the tile.
+    pub fn apply(&self, point: Vec2<f32>) -> Vec2<f32> {
+        let mut point = if self.flip_d {
+            Vec2::new(point.y, point.x)
+        } else {
+            point
+        };
+        if self.flip_h {
+            point.x = -point.x;
+        }
+        if self.flip_v {
+            point.y = -point.y;
+        }
+        point
+    }
+
+    // Width and height of a box once transformed.
+    pub fn apply_size(&self, size: Vec2<f32>) -> Vec2<f32> {
+        if self.flip_d {
+            Vec2::new(size.y, size.x)
+        } else {
+            size
+        }
+    }
+
+    // DrawParams scales before it rotates, so the transform is split into a
+    // mirror followed by an optional quarter turn.
+    pub fn rotation(&self) -> f32 {
+        if self.flip_d {
+            FRAC_PI_2
+        } else {
+            0.0
+        }
+    }
+
+    pub fn scale(&self) -> Vec2<f32> {
+        let h = if self.flip_h { -1.0 } else { 1.0 };
+        let v = if self.flip_v { -1.0 } else { 1.0 };
+        if self.flip_d {
+            Vec2::new(v, -h)
+        } else {
+            Vec2::new(h, v)
+        }
+    }
+}
+
+// A Tiled <animation>. There is no per-instance state: the current frame is
+// derived from the game clock, so every tile using it shows the same frame.
+#[derive(Debug, Clone)]
+pub struct TileAnimation {
+    pub frames: Vec<(Rectangle, Duration)>,
+    pub length: Duration,
+}
+
+impl TileAnimation {
+    pub fn new(frames: Vec<(Rectangle, Duration)>) -> TileAnimation {
+        let length = frames.iter().map(|(_, duration)| *duration).sum();
+        TileAnimation { frames, length }
+    }
+
+    pub fn frame(&self, clock: Duration) -> Option<Rectangle> {
+        if self.length.as_nanos() == 0 {
+            return self.frames.first().map(|(rect, _)| *rect);
+        }
+        let mut time = clock.as_nanos() % self.length.as_nanos();
+        for (rect, duration) in &self.frames {
+            if time < duration.as_nanos() {
+                return Some(*rect);
+            }
+            time -= duration.as_nanos();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all() -> Vec<TileTransform> {
+        let mut transforms = Vec::new();
+        for &flip_d in &[false, true] {
+            for &flip_h in &[false, true] {
+                for &flip_v in &[false, true] {
+                    transforms.push(TileTransform {
+                        flip_h,
+                        flip_v,
+                        flip_d,
+                    });
+                }
+            }
+        }
+        transforms
+    }
+
+    fn transform(flip_h: bool, flip_v: bool, flip_d: bool) -> TileTransform {
+        TileTransform {
+            flip_h,
+            flip_v,
+            flip_d,
+        }
+    }
+
+    // What DrawParams does with our scale and rotation.
+    fn draw(transform: &TileTransform, point: Vec2<f32>) -> Vec2<f32> {
+        let scaled = point * transform.scale();
+        let (sin, cos) = transform.rotation().sin_cos();
+        Vec2::new(
+            scaled.x * cos - scaled.y * sin,
+            scaled.x * sin + scaled.y * cos,
+        )
+    }
+
+    fn assert_close(a: Vec2<f32>, b: Vec2<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn no_flags_is_identity() {
+        let t = transform(false, false, false);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(3.0, -2.0));
+        assert_eq!(t.rotation(), 0.0);
+        assert_eq!(t.scale(), Vec2::new(1.0, 1.0));
+    }
+
+    #[test]
+    fn flip_h_mirrors_x() {
+        let t = transform(true, false, false);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(-3.0, -2.0));
+    }
+
+    #[test]
+    fn flip_v_mirrors_y() {
+        let t = transform(false, true, false);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(3.0, 2.0));
+    }
+
+    #[test]
+    fn flip_h_and_v_is_half_turn() {
+        let t = transform(true, true, false);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(-3.0, 2.0));
+    }
+
+    #[test]
+    fn flip_d_swaps_axes() {
+        let t = transform(false, false, true);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(-2.0, 3.0));
+    }
+
+    #[test]
+    fn flip_d_and_h_is_clockwise_turn() {
+        // y points down, so clockwise takes right to down
+        let t = transform(true, false, true);
+        assert_close(t.apply(Vec2::new(1.0, 0.0)), Vec2::new(0.0, 1.0));
+        assert_close(t.apply(Vec2::new(0.0, 1.0)), Vec2::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn flip_d_and_v_is_anticlockwise_turn() {
+        let t = transform(false, true, true);
+        assert_close(t.apply(Vec2::new(1.0, 0.0)), Vec2::new(0.0, -1.0));
+        assert_close(t.apply(Vec2::new(0.0, 1.0)), Vec2::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn flip_all_is_anti_diagonal_mirror() {
+        let t = transform(true, true, true);
+        assert_close(t.apply(Vec2::new(3.0, -2.0)), Vec2::new(2.0, -3.0));
+    }
+
+    #[test]
+    fn draw_params_match_transform() {
+        let points = [
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+            Vec2::new(5.0, -3.0),
+        ];
+        for t in all() {
+            for &point in &points {
+                assert_close(draw(&t, point), t.apply(point));
+            }
+        }
+    }
+
+    #[test]
+    fn size_swaps_only_on_diagonal() {
+        for t in all() {
+            let size = t.apply_size(Vec2::new(4.0, 2.0));
+            if t.flip_d {
+                assert_eq!(size, Vec2::new(2.0, 4.0));
+            } else {
+                assert_eq!(size, Vec2::new(4.0, 2.0));
+            }
+        }
+    }
+
+    #[test]
+    fn animation_follows_clock() {
+        let a = Rectangle::new(0.0, 0.0, 16.0, 16.0);
+        let b = Rectangle::new(16.0, 0.0, 16.0, 16.0);
+        let animation = TileAnimation::new(vec![
+            (a, Duration::from_millis(250)),
+            (b, Duration::from_millis(500)),
+        ]);
+        assert_eq!(animation.frame(Duration::from_millis(0)), Some(a));
+        assert_eq!(animation.frame(Duration::from_millis(300)), Some(b));
+        assert_eq!(animation.frame(Duration::from_millis(800)), Some(a));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use ncollide2d::query::Proximity;
+use nphysics2d::object::{
+    BodyPartHandle, BodyStatus, ColliderDesc, DefaultBodySet, DefaultColliderHandle,
+    DefaultColliderSet, RigidBodyDesc,
+};
+use nphysics2d::world::DefaultGeometricalWorld;
+use tetra::math::Vec2;
+
+use crate::collision::object_shapes;
+use crate::components::{Trigger, TriggerEvent, TriggerPhase};
+use crate::tile::TileTransform;
+
+// Turns a `Trigger` object into sensor colliders. Sensors only report
+// proximity, so nothing ever bumps into them.
+pub fn spawn_trigger(
+    object: &tiled::Object,
+    colliders: &mut DefaultColliderSet<f32>,
+    bodies: &mut DefaultBodySet<f32>,
+    world: &mut World,
+) -> Entity {
+    let body = RigidBodyDesc::new()
+        .gravity_enabled(false)
+        .status(BodyStatus::Static)
+        .build();
+    let body_handle = bodies.insert(body);
+    let untransformed = TileTransform {
+        flip_h: false,
+        flip_v: false,
+        flip_d: false,
+    };
+    // with no tile to be relative to, the offsets are map positions
+    let handles = object_shapes(object, &untransformed, Vec2::zero())
+        .into_iter()
+        .map(|(offset, shape)| {
+            let collider = ColliderDesc::new(shape)
+                .translation(offset)
+                .sensor(true)
+                .build(BodyPartHandle(body_handle, 0));
+            colliders.insert(collider)
+        })
+        .collect();
+    world.spawn((Trigger {
+        name: object.name.clone(),
+        properties: object.properties.clone(),
+        colliders: handles,
+    },))
+}
+
+// Collects the enters and exits reported by the last physics step.
+pub fn trigger_events(
+    geometrical_world: &DefaultGeometricalWorld<f32>,
+    entities: &HashMap<DefaultColliderHandle, Entity>,
+    world: &World,
+) -> Vec<TriggerEvent> {
+    let mut events = Vec::new();
+    for event in geometrical_world.proximity_events() {
+        let phase = match (event.prev_status, event.new_status) {
+            (Proximity::Intersecting, Proximity::Intersecting) => continue,
+            (_, Proximity::Intersecting) => TriggerPhase::Enter,
+            (Proximity::Intersecting, _) => TriggerPhase::Exit,
+            _ => continue,
+        };
+        let entity1 = entities.get(&event.collider1).copied();
+        let entity2 = entities.get(&event.collider2).copied();
+        for &(trigger, other) in &[(entity1, entity2), (entity2, entity1)] {
+            let trigger = match trigger {
+                Some(trigger) => trigger,
+                None => continue,
+            };
+            if let Ok(data) = world.get::<Trigger>(trigger) {
+                events.push(TriggerEvent {
+                    phase,
+                    trigger,
+                    name: data.name.clone(),
+                    properties: data.properties.clone(),
+                    other,
+                });
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{BodyHandle, Player, TriggerPhase};
+    use crate::contact::collider_entities;
+    use crate::map::LoadedMap;
+    use crate::player::InputState;
+    use crate::simulation::{seeded, Simulation};
+    use nalgebra::base::Vector2;
+    use nalgebra::geometry::Isometry2;
+    use tiled::ObjectShape;
+
+    // A 32 by 32 plate centred on `center`.
+    fn plate(center: Vec2<f32>) -> tiled::Object {
+        tiled::Object {
+            id: 1,
+            gid: 0,
+            name: "plate".to_string(),
+            obj_type: "Trigger".to_string(),
+            width: 32.0,
+            height: 32.0,
+            x: center.x - 16.0,
+            y: center.y - 16.0,
+            rotation: 0.0,
+            visible: true,
+            shape: ObjectShape::Rect {
+                width: 32.0,
+                height: 32.0,
+            },
+            properties: HashMap::new(),
+        }
+    }
+
+    fn teleport_player(sim: &mut Simulation, to: Vec2<f32>) {
+        for (_id, (_player, handle)) in &mut sim.world.query::<(&Player, &BodyHandle)>() {
+            let body = sim.body_set.rigid_body_mut(handle.0).unwrap();
+            body.set_position(Isometry2::new(Vector2::new(to.x, to.y), nalgebra::zero()));
+        }
+    }
+
+    fn events_over(sim: &mut Simulation, ticks: usize) -> Vec<TriggerEvent> {
+        let idle = InputState::default();
+        let mut events = Vec::new();
+        for _ in 0..ticks {
+            sim.step(&idle);
+            events.append(&mut sim.trigger_events);
+        }
+        events
+    }
+
+    #[test]
+    fn entering_and_leaving_are_reported_once() {
+        let mut sim = seeded(None);
+        // no NPCs to wander onto the plate
+        sim.build_level(LoadedMap::load(None).unwrap(), None, false).unwrap();
+        events_over(&mut sim, 1);
+        let home = sim.player_position().unwrap();
+        let player = sim.world.query::<&Player>().iter().next().unwrap().0;
+        // away from the plate, then onto it
+        teleport_player(&mut sim, home + Vec2::new(128.0, 0.0));
+        let trigger = spawn_trigger(
+            &plate(home),
+            &mut sim.collider_set,
+            &mut sim.body_set,
+            &mut sim.world,
+        );
+        sim.collider_entities = collider_entities(&sim.world, &sim.collider_set);
+        assert!(events_over(&mut sim, 10).is_empty());
+
+        teleport_player(&mut sim, home);
+        let entered = events_over(&mut sim, 10);
+        assert_eq!(entered.len(), 1);
+        assert_eq!(entered[0].phase, TriggerPhase::Enter);
+        assert_eq!(entered[0].trigger, trigger);
+        assert_eq!(entered[0].name, "plate");
+        assert_eq!(entered[0].other, Some(player));
+
+        teleport_player(&mut sim, home + Vec2::new(128.0, 0.0));
+        let left = events_over(&mut sim, 10);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].phase, TriggerPhase::Exit);
+        assert_eq!(left[0].trigger, trigger);
+        assert_eq!(left[0].other, Some(player));
+    }
+}
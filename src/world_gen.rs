@@ -1,184 +1,169 @@
-use crate::components::{AnimationData, SpawnBounds, Sprite};
+use crate::collision::{merge_cells, object_shapes};
+use crate::components::{Direction, NPCState, SpawnBounds, Sprite, Warp};
 use crate::npc::spawn_npcs;
-use crate::player::new_player;
+use crate::prefab::{object_prefab, spawn_prefab, Prefabs, Spawner, NPC_PREFAB, PLAYER_PREFAB};
+use crate::tile::TileTransform;
+use crate::trigger::spawn_trigger;
 use nalgebra::base::Vector2;
-use nalgebra::geometry::Isometry2;
 
 use ncollide2d::pipeline::CollisionGroups;
 
-use hecs::World;
 use ncollide2d::shape::{Cuboid, ShapeHandle};
 use nphysics2d::object::{
-    BodyPartHandle, BodyStatus, ColliderDesc, DefaultBodySet, DefaultColliderSet, RigidBodyDesc,
+    BodyPartHandle, BodyStatus, ColliderDesc, DefaultBodyHandle, DefaultBodySet,
+    DefaultColliderSet, RigidBodyDesc,
 };
-use tetra::Context;
+use tetra::math::Vec2;
 
 use std::collections::HashMap;
 
-use tiled::ObjectShape;
+use rand::rngs::StdRng;
 
-use tiled::PropertyValue::IntValue;
+use tiled::PropertyValue::{IntValue, StringValue};
 
 pub fn create_map_bounds(
     lyr: &tiled::Layer,
+    tile_size: Vec2<f32>,
     colliders: &mut DefaultColliderSet<f32>,
     bodies: &mut DefaultBodySet<f32>,
-) {
+) -> DefaultBodyHandle {
     let mut tile_group = CollisionGroups::new();
     tile_group.set_membership(&[3]);
     tile_group.set_whitelist(&[1]);
-    let y_max = lyr.tiles.len() - 1;
-    for (y, row) in lyr.tiles.iter().enumerate().clone() {
-        let x_max = row.len() - 1;
+    let height = lyr.tiles.len();
+    let width = lyr.tiles.first().map_or(0, |row| row.len());
+    // the layer plus a ring of cells around it, so cell (x, y) is at (x + 1, y + 1)
+    let mut solid = vec![vec![false; width + 2]; height + 2];
+    for (y, row) in lyr.tiles.iter().enumerate() {
         for (x, &tile) in row.iter().enumerate() {
-            let shape = ShapeHandle::new(Cuboid::new(Vector2::repeat(8.0 - 0.01)));
             if tile.gid == 0 {
-                let shape_pos = Isometry2::new(
-                    Vector2::new((x as f32 * 16.0) + 8.0, (y as f32 * 16.0) + 8.0),
-                    nalgebra::zero(),
-                );
-                let world_body = RigidBodyDesc::new()
-                    .position(shape_pos)
-                    .gravity_enabled(false)
-                    .status(BodyStatus::Static)
-                    .build();
-
-                let world_body_handle = bodies.insert(world_body);
-
-                let world_body_collider =
-                    ColliderDesc::new(shape.clone()).build(BodyPartHandle(world_body_handle, 0));
-
-                colliders.insert(world_body_collider);
+                solid[y + 1][x + 1] = true;
                 continue;
             }
             if y == 0 {
-                let shape_pos = Isometry2::new(
-                    Vector2::new((x as f32 * 16.0) + 8.0, (y as f32 * 16.0) - 8.0),
-                    nalgebra::zero(),
-                );
-                let world_body = RigidBodyDesc::new()
-                    .position(shape_pos)
-                    .gravity_enabled(false)
-                    .status(BodyStatus::Static)
-                    .build();
-
-                let world_body_handle = bodies.insert(world_body);
-
-                let world_body_collider =
-                    ColliderDesc::new(shape.clone()).build(BodyPartHandle(world_body_handle, 0));
-
-                colliders.insert(world_body_collider);
-            } else if y == y_max {
-                let shape_pos = Isometry2::new(
-                    Vector2::new((x as f32 * 16.0) + 8.0, ((y as f32 + 1.0) * 16.0) + 8.0),
-                    nalgebra::zero(),
-                );
-                let world_body = RigidBodyDesc::new()
-                    .position(shape_pos)
-                    .gravity_enabled(false)
-                    .status(BodyStatus::Static)
-                    .build();
-
-                let world_body_handle = bodies.insert(world_body);
-
-                let world_body_collider =
-                    ColliderDesc::new(shape.clone()).build(BodyPartHandle(world_body_handle, 0));
-
-                colliders.insert(world_body_collider);
+                solid[0][x + 1] = true;
+            } else if y == height - 1 {
+                solid[height + 1][x + 1] = true;
             }
-
             if x == 0 {
-                let shape_pos = Isometry2::new(
-                    Vector2::new((x as f32 * 16.0) - 8.0, (y as f32 * 16.0) + 8.0),
-                    nalgebra::zero(),
-                );
-                let world_body = RigidBodyDesc::new()
-                    .position(shape_pos)
-                    .gravity_enabled(false)
-                    .status(BodyStatus::Static)
-                    .build();
-
-                let world_body_handle = bodies.insert(world_body);
-
-                let world_body_collider =
-                    ColliderDesc::new(shape.clone()).build(BodyPartHandle(world_body_handle, 0));
-
-                colliders.insert(world_body_collider);
-            } else if x == x_max {
-                let shape_pos = Isometry2::new(
-                    Vector2::new(((x as f32 + 1.0) * 16.0) + 8.0, (y as f32 * 16.0) + 8.0),
-                    nalgebra::zero(),
-                );
-                let world_body = RigidBodyDesc::new()
-                    .position(shape_pos)
-                    .gravity_enabled(false)
-                    .status(BodyStatus::Static)
-                    .build();
-
-                let world_body_handle = bodies.insert(world_body);
-
-                let world_body_collider =
-                    ColliderDesc::new(shape.clone()).build(BodyPartHandle(world_body_handle, 0));
-
-                colliders.insert(world_body_collider);
+                solid[y + 1][0] = true;
+            } else if x == width - 1 {
+                solid[y + 1][width + 1] = true;
             }
         }
     }
+
+    let world_body = RigidBodyDesc::new()
+        .gravity_enabled(false)
+        .status(BodyStatus::Static)
+        .build();
+    let world_body_handle = bodies.insert(world_body);
+    for rect in merge_cells(&solid) {
+        let size = Vec2::new(
+            rect.width as f32 * tile_size.x,
+            rect.height as f32 * tile_size.y,
+        );
+        let top_left = Vec2::new(
+            (rect.x as f32 - 1.0) * tile_size.x,
+            (rect.y as f32 - 1.0) * tile_size.y,
+        );
+        let center = top_left + size / 2.0;
+        let shape = ShapeHandle::new(Cuboid::new(Vector2::new(
+            size.x / 2.0 - 0.01,
+            size.y / 2.0 - 0.01,
+        )));
+        let world_body_collider = ColliderDesc::new(shape)
+            .translation(Vector2::new(center.x, center.y))
+            .build(BodyPartHandle(world_body_handle, 0));
+
+        colliders.insert(world_body_collider);
+    }
+    world_body_handle
 }
 pub fn spawn(
-    colliders: &mut DefaultColliderSet<f32>,
-    bodies: &mut DefaultBodySet<f32>,
-    world: &mut World,
-    sheet_lens: (&usize, &usize),
-    anim_data: &AnimationData,
+    spawner: &mut Spawner,
+    prefabs: &Prefabs,
     map: &tiled::Map,
-    ctx: &mut Context,
+    player_spawn: Option<&str>,
+    rng: &mut StdRng,
+    with_npcs: bool,
 ) {
     if !map.object_groups.is_empty() {
         for object_group in &map.object_groups {
             for object in &object_group.objects {
-                if object.obj_type == "NPCSpawn" {
+                if object.obj_type == "NPCSpawn" && with_npcs {
                     let bounds = SpawnBounds {
                         x: (object.x, object.x + object.width),
                         y: (object.y, object.y + object.height),
                     };
-                    if let Some(IntValue(count)) = object.properties.get("count") {
-                        spawn_npcs(
-                            *count as u32,
-                            colliders,
-                            bodies,
-                            world,
-                            *sheet_lens.0,
-                            anim_data.clone(),
-                            &bounds,
-                        );
+                    let prefab = prefabs.get(object_prefab(object, NPC_PREFAB));
+                    if let (Some(IntValue(count)), Some(prefab)) =
+                        (object.properties.get("count"), prefab)
+                    {
+                        spawn_npcs(*count as u32, prefab, spawner, &bounds, rng);
                     }
                 }
-                if object.obj_type == "PlayerSpawn" {
-                    let pos = Vector2::new(object.x + 8.0, object.y + 8.0);
-                    new_player(
-                        ctx,
-                        world,
-                        *sheet_lens.1,
-                        bodies,
-                        colliders,
-                        anim_data.clone(),
-                        &pos,
-                    );
+                if object.obj_type == "Warp" || object.obj_type == "Door" {
+                    if let Some(StringValue(target)) = object.properties.get("map") {
+                        let spawn = match object.properties.get("spawn") {
+                            Some(StringValue(name)) => Some(name.clone()),
+                            _ => None,
+                        };
+                        spawner.world.spawn((Warp {
+                            bounds: SpawnBounds {
+                                x: (object.x, object.x + object.width),
+                                y: (object.y, object.y + object.height),
+                            },
+                            map: target.clone(),
+                            spawn,
+                        },));
+                    }
+                }
+                if object.obj_type == "Trigger" {
+                    spawn_trigger(object, spawner.colliders, spawner.bodies, spawner.world);
                 }
             }
         }
     }
+    // arriving through a warp puts the player on the object it names,
+    // otherwise they start on the map's PlayerSpawn
+    let objects = || map.object_groups.iter().flat_map(|group| group.objects.iter());
+    let player_object = player_spawn
+        .and_then(|name| objects().find(|object| object.name == name))
+        .or_else(|| objects().find(|object| object.obj_type == "PlayerSpawn"));
+    let player = player_object.and_then(|object| {
+        let prefab = prefabs.get(object_prefab(object, PLAYER_PREFAB))?;
+        Some((object, prefab))
+    });
+    if let Some((object, prefab)) = player {
+        let pos = Vector2::new(object.x + 8.0, object.y + 8.0);
+        let character = prefab.character(spawner.sheets, rng);
+        spawn_prefab(
+            prefab,
+            pos,
+            character,
+            Direction::Down,
+            NPCState::Idle,
+            spawner,
+        );
+    }
 }
 pub fn create_physics_world(
     lyrs: &Vec<tiled::Layer>,
+    tile_size: Vec2<f32>,
     sprite_map: &HashMap<u32, Sprite>,
     colliders: &mut DefaultColliderSet<f32>,
     bodies: &mut DefaultBodySet<f32>,
-) {
+) -> DefaultBodyHandle {
     let mut tile_group = CollisionGroups::new();
     tile_group.set_membership(&[3]);
     tile_group.set_whitelist(&[1]);
+    // every tile collider hangs off this one static body
+    let world_body = RigidBodyDesc::new()
+        .gravity_enabled(false)
+        .status(BodyStatus::Static)
+        .build();
+    let world_body_handle = bodies.insert(world_body);
     for lyr in lyrs {
         for (y, row) in lyr.tiles.iter().enumerate().clone() {
             for (x, &tile) in row.iter().enumerate() {
@@ -187,58 +172,24 @@ pub fn create_physics_world(
                 }
                 let gid = tile.gid;
                 let sprite = sprite_map.get(&gid).unwrap();
-                let mut rotation: f32 = 0.0;
-                if tile.flip_h {
-                    rotation += 180.0;
-                }
-                if tile.flip_d {
-                    rotation -= 90.0;
-                }
-                if tile.flip_v {
-                    rotation -= 0.0;
-                }
+                let transform = TileTransform::from_tile(&tile);
                 if sprite.collision_objects.is_some() {
                     let objs = sprite.collision_objects.as_ref().unwrap();
-                    
+                    let half_sprite = Vec2::new(sprite.width / 2.0, sprite.height / 2.0);
+                    let center = sprite.top_left(x, y, tile_size) + half_sprite;
+
                     for obj in objs {
-                        let dimensions: Option<(&f32, &f32)> = match &obj.shape {
-                            ObjectShape::Rect { width, height } => Some((width, height)),
-                            _ => None,
-                        };
-                        let mut height: f32 = 0.0;
-                        let mut width: f32 = 0.0;
-                        if dimensions.is_some() {
-                            width = dimensions.unwrap().0.clone();
-                            height = dimensions.unwrap().1.clone();
-                        }
-                        let shape =
-                            ShapeHandle::new(Cuboid::new(Vector2::new(width / 2.0, height / 2.0)));
-                        let mut translator: (f32, f32);
-                        match rotation {
-                            0.0 => translator = (obj.x, obj.y),
-                            90.0 => translator = (obj.y / 2.0, obj.x / 2.0),
-                            -90.0 => translator = (obj.y / 2.0, -obj.x / 2.0),
-                            180.0 => translator = (-obj.x / 2.0, -obj.y / 2.0),
-                            _ => translator = (obj.x, obj.y),
-                        }
-                        let world_body = RigidBodyDesc::new()
-                            .translation(Vector2::new(
-                                (x as f32 * 16.0) + (width / 2.0) + translator.0,
-                                (y as f32 * 16.0) + (height / 2.0) + translator.1,
-                            ))
-                            .rotation(nalgebra::zero())
-                            .gravity_enabled(false)
-                            .status(BodyStatus::Static)
-                            .build();
-                        let world_body_handle = bodies.insert(world_body);
-                        let world_body_collider = ColliderDesc::new(shape)
-                            .rotation(rotation.to_radians())
-                            .build(BodyPartHandle(world_body_handle, 0));
+                        for (offset, shape) in object_shapes(obj, &transform, half_sprite) {
+                            let world_body_collider = ColliderDesc::new(shape)
+                                .translation(Vector2::new(center.x, center.y) + offset)
+                                .build(BodyPartHandle(world_body_handle, 0));
 
-                        colliders.insert(world_body_collider);
+                            colliders.insert(world_body_collider);
+                        }
                     }
                 }
             }
         }
     }
+    world_body_handle
 }